@@ -0,0 +1,53 @@
+// AudioWorklet that plays the samples index.js copies out of the game's
+// audio buffer. Frames and the audio clock never quite agree, so it waits
+// for a little to build up first, and drops the oldest samples when far behind.
+const PREBUFFER_SECONDS = 0.05;
+const MAX_QUEUED_SECONDS = 0.2;
+
+class GameAudioProcessor extends AudioWorkletProcessor {
+    constructor() {
+        super();
+        this.chunks = [];
+        // Samples of chunks[0] already played
+        this.offset = 0;
+        this.queued = 0;
+        this.playing = false;
+        this.port.onmessage = e => {
+            this.chunks.push(e.data);
+            this.queued += e.data.length;
+            while (this.chunks.length > 1 && this.queued > MAX_QUEUED_SECONDS * sampleRate) {
+                this.queued -= this.chunks[0].length - this.offset;
+                this.chunks.shift();
+                this.offset = 0;
+            }
+        };
+    }
+
+    process(inputs, outputs) {
+        const output = outputs[0][0];
+        if (!this.playing && this.queued < PREBUFFER_SECONDS * sampleRate) {
+            return true;
+        }
+        this.playing = true;
+        let i = 0;
+        while (i < output.length && this.chunks.length > 0) {
+            const chunk = this.chunks[0];
+            const count = Math.min(output.length - i, chunk.length - this.offset);
+            output.set(chunk.subarray(this.offset, this.offset + count), i);
+            i += count;
+            this.offset += count;
+            this.queued -= count;
+            if (this.offset === chunk.length) {
+                this.chunks.shift();
+                this.offset = 0;
+            }
+        }
+        // Ran dry, the rest stays silent until enough has built up again
+        if (i < output.length) {
+            this.playing = false;
+        }
+        return true;
+    }
+}
+
+registerProcessor("game-audio", GameAudioProcessor);
@@ -1,23 +1,87 @@
 #![no_main]
 #![no_std]
 
+use core::fmt::{self, Write};
+#[cfg(target_arch = "wasm32")]
 use core::panic::PanicInfo;
 
+mod songs;
+mod strings;
+use songs::{Song, SONGS_COUNT, SONG_CELL_SIZE, SONG_HEADER_SIZE};
+use strings::{Language, Message, LANGUAGES_COUNT};
+
+// Native hosts link against std, which brings its own panic handler.
+// Here the host gets the location and message, then the module traps so the
+// host's call into it fails instead of hanging.
+#[cfg(target_arch = "wasm32")]
 #[panic_handler]
-fn panic(_panic: &PanicInfo<'_>) -> ! {
-    loop {}
+fn panic(info: &PanicInfo<'_>) -> ! {
+    let mut message: Label<PANIC_MESSAGE_CAPACITY> = Label::empty();
+    // Whatever doesn't fit is cut off
+    let _ = match info.location() {
+        Some(location) => write!(message, "panicked at {}:{}:{}: {}",
+                                 location.file(), location.line(), location.column(), info.message()),
+        None => write!(message, "panicked: {}", info.message()),
+    };
+    unsafe { host_panic(message.bytes().as_ptr(), message.bytes().len()) };
+    core::arch::wasm32::unreachable()
+}
+
+#[derive(Clone, Copy)]
+#[repr(u32)]
+enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+// log!(LogLevel::Info, "score {}", score) formats like write! and hands the
+// line to the host through host_log. Building with `--cfg no_log` compiles
+// every call out, arguments and all.
+#[cfg(not(no_log))]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        log_line($level, format_args!($($arg)*))
+    };
+}
+
+#[cfg(no_log)]
+macro_rules! log {
+    // Still type checks the arguments, so nothing turns unused
+    ($level:expr, $($arg:tt)*) => {
+        if false {
+            let _ = ($level, format_args!($($arg)*));
+        }
+    };
+}
+
+#[cfg(not(no_log))]
+fn log_line(level: LogLevel, args: fmt::Arguments<'_>) {
+    let mut line: Label<LOG_LINE_CAPACITY> = Label::empty();
+    // A line cut short is still worth logging
+    let _ = line.write_fmt(args);
+    unsafe { host_log(level as u32, line.bytes().as_ptr(), line.bytes().len()) };
 }
 
 // --- Constants ---
 const DISPLAY_WIDTH: usize = 800;
 const DISPLAY_HEIGHT: usize = 600;
 const DISPLAY_BACKGROUND: Pixel = Pixel::rgba(0x1E, 0x1E, 0x2E, 0xFF);
+const DIRTY_RECTS_CAPACITY: usize = 64;
+const DIRTY_RECT_MERGE_DISTANCE: i32 = 16;
+#[cfg(target_arch = "wasm32")]
+const PANIC_MESSAGE_CAPACITY: usize = 256;
+#[cfg(not(no_log))]
+const LOG_LINE_CAPACITY: usize = 256;
 
 // Player
 const PLAYER_SIZE: i32 = 80;
 const PLAYER_COLOR: Pixel = Pixel::rgba(0x00, 0xA2, 0xFF, 0xFF);
 const PLAYER_KILL_REWARD: usize = 100;
 const PLAYER_INITIAL_HEALTH: i32 = 3;
+// Pixels per second with the stick all the way over
+const PLAYER_MAX_SPEED: f32 = 900.0;
 
 // Bullet
 const BULLET_SIZE: i32 = 25;
@@ -36,33 +100,270 @@ const ENEMY_SPAWN_PERIOD_SCORE_FACTOR: f32 = 0.001;
 
 // UI & Text
 const SCORE_LABEL_COLOR: Pixel = Pixel::rgba(0xDC, 0xDC, 0xCC, 0xFF);
-const HEALTH_LABEL_COLOR: Pixel = Pixel::rgba(0xDA, 0x47, 0x50, 0xFF);
 const MESSAGE_COLOR: Pixel = Pixel::rgba(0xFF, 0xFF, 0xFF, 0xFF);
 const SCORE_LABEL_PADDING: i32 = 17;
 const SCORE_LABEL_X: i32 = SCORE_LABEL_PADDING;
 const SCORE_LABEL_Y: i32 = SCORE_LABEL_PADDING;
-const HEALTH_LABEL_X: i32 = SCORE_LABEL_PADDING;
-const HEALTH_LABEL_Y: i32 = SCORE_LABEL_PADDING * 2 + FONT_CHAR_HEIGHT as i32 * 4;
 const TEXT_SCALE: i32 = 4;
-const MESSAGE_SCALE: i32 = 6;
+const HUD_SCALE: i32 = 6;
+const MESSAGE_SCALE: i32 = 3;
+
+// HUD
+// Share of the remaining gap the score counter closes per second
+const SCORE_COUNT_RATE: f32 = 6.0;
+// Slowest the counter ever moves, in points per second
+const SCORE_COUNT_MIN_SPEED: f32 = 200.0;
+const HEARTS_X: i32 = SCORE_LABEL_PADDING;
+const HEART_COLOR: Pixel = Pixel::rgba(0xDA, 0x47, 0x50, 0xFF);
+const HEART_EMPTY_COLOR: Pixel = Pixel::rgba(0x45, 0x45, 0x5A, 0xFF);
+// One row per byte, most significant of HEART_ICON_WIDTH bits on the left
+const HEART_ICON: [u8; 6] = [0b0110110, 0b1111111, 0b1111111, 0b0111110, 0b0011100, 0b0001000];
+const HEART_ICON_WIDTH: i32 = 7;
+const HEART_SCALE: i32 = 5;
+const HEART_SPACING: i32 = 8;
+// A lost heart swells by this much scale while it fades out
+const HEART_POP_GROWTH: i32 = 3;
+const HEART_POP_DURATION: Seconds = 0.4;
+
+// Score popups
+const POPUPS_CAPACITY: usize = 8;
+const POPUP_DURATION: Seconds = 0.9;
+const POPUP_RISE: i32 = 60;
+const POPUP_SCALE: i32 = 4;
+const POPUP_COLOR: Pixel = Pixel::rgba(0xFF, 0xC1, 0x00, 0xFF);
+// Fading is stepped so each popup only ever needs a few cached glyph colours
+const POPUP_FADE_STEPS: u32 = 4;
 
 // Shadow
 const SHADOW_COLOR: Pixel = Pixel::rgba(0x2B, 0x2B, 0x2B, 0xFF);
 const SHADOW_OFFSET: i32 = 4;
 
+// Camera & Effects
+const SHAKE_DURATION: Seconds = 0.35;
+const SHAKE_PLAYER_HIT_MAGNITUDE: i32 = 14;
+const SHAKE_ENEMY_KILL_MAGNITUDE: i32 = 4;
+const HITSTOP_DURATION: Seconds = 0.08;
+const FLASH_DURATION: Seconds = 0.4;
+const FLASH_COLOR: Pixel = Pixel::rgba(0xFF, 0x00, 0x00, 0xFF);
+const FLASH_MAX_ALPHA: f32 = 0.6;
+const VIGNETTE_BANDS: i32 = 6;
+const VIGNETTE_BAND_SIZE: i32 = 10;
+
+// Input
+// Events the host can queue between two frames
+const INPUT_QUEUE_CAPACITY: usize = 256;
+// The browser's KeyboardEvent.keyCode values
+const KEY_BACKSPACE: i32 = 8;
+const KEY_TAB: i32 = 9;
+const KEY_ENTER: i32 = 13;
+const KEY_SHIFT: i32 = 16;
+const KEY_CTRL: i32 = 17;
+const KEY_ALT: i32 = 18;
+const KEY_ESCAPE: i32 = 27;
+const KEY_SPACE: i32 = 32;
+const KEY_LEFT: i32 = 37;
+const KEY_UP: i32 = 38;
+const KEY_RIGHT: i32 = 39;
+const KEY_DOWN: i32 = 40;
+const KEY_0: i32 = 48;
+const KEY_9: i32 = 57;
+const KEY_A: i32 = 65;
+const KEY_C: i32 = 67;
+// Switches languages in index.js and never reaches the game there
+const KEY_L: i32 = 76;
+const KEY_Z: i32 = 90;
+// MouseEvent.button values
+const MOUSE_BUTTON_LEFT: i32 = 0;
+
+// Gamepad, numbered as in the browser's standard gamepad mapping
+const GAMEPAD_AXIS_LEFT_X: i32 = 0;
+const GAMEPAD_BUTTON_A: i32 = 0;
+const GAMEPAD_BUTTON_B: i32 = 1;
+const GAMEPAD_BUTTON_RIGHT_TRIGGER: i32 = 7;
+const GAMEPAD_BUTTON_BACK: i32 = 8;
+const GAMEPAD_BUTTON_START: i32 = 9;
+const GAMEPAD_BUTTON_DPAD_UP: i32 = 12;
+const GAMEPAD_BUTTON_DPAD_DOWN: i32 = 13;
+const GAMEPAD_BUTTON_DPAD_LEFT: i32 = 14;
+const GAMEPAD_BUTTON_DPAD_RIGHT: i32 = 15;
+const GAMEPAD_BUTTON_NAMES: [&str; 17] = [
+    "A", "B", "X", "Y", "LB", "RB", "LT", "RT", "Back", "Start",
+    "L3", "R3", "D-Up", "D-Down", "D-Left", "D-Right", "Home",
+];
+// Gamepad buttons are tracked as bits of a u32
+const GAMEPAD_BUTTONS_COUNT: i32 = 32;
+// Stick deflection ignored around the centre, where worn sticks drift
+const DEFAULT_STICK_DEAD_ZONE: f32 = 0.15;
+// How far a trigger must be pulled to count as pressed
+const DEFAULT_TRIGGER_THRESHOLD: f32 = 0.5;
+
+// Controls
+// A key or button slot with nothing in it
+const NO_BINDING: i32 = -1;
+const CONTROLS_SCALE: i32 = 3;
+const CONTROLS_TOP: i32 = 170;
+const CONTROLS_ROW_HEIGHT: i32 = 60;
+const CONTROLS_KEY_X: i32 = 400;
+const CONTROLS_BUTTON_X: i32 = 600;
+const CONTROLS_SELECTED_COLOR: Pixel = Pixel::rgba(0x45, 0x45, 0x5A, 0xFF);
+const CONTROLS_SELECTED_ALPHA: u32 = 160;
+const CONTROLS_WAITING_COLOR: Pixel = Pixel::rgba(0xFF, 0xC1, 0x00, 0xFF);
+const BINDING_NAME_CAPACITY: usize = 16;
+
+// Touch
+// Seconds between shots while a finger steers the player
+const TOUCH_FIRE_PERIOD: Seconds = 0.25;
+const PAUSE_BUTTON_SIZE: i32 = 64;
+const PAUSE_BUTTON: Rect = Rect::new(
+    DISPLAY_WIDTH as i32 - SCORE_LABEL_PADDING - PAUSE_BUTTON_SIZE,
+    SCORE_LABEL_PADDING,
+    PAUSE_BUTTON_SIZE,
+    PAUSE_BUTTON_SIZE,
+);
+const PAUSE_BUTTON_COLOR: Pixel = Pixel::rgba(0x45, 0x45, 0x5A, 0xFF);
+const PAUSE_BUTTON_ALPHA: u32 = 160;
+const PAUSE_BUTTON_BAR_WIDTH: i32 = 12;
+
+// Transitions
+const TRANSITION_DURATION: Seconds = 0.8;
+const TRANSITION_COLOR: Pixel = Pixel::rgba(0x00, 0x00, 0x00, 0xFF);
+const DISSOLVE_BLOCK_SIZE: i32 = 10;
+// A level screen comes up every time the score goes up by this much
+const LEVEL_SCORE_STEP: usize = 1000;
+const LEVEL_SCREEN_DURATION: Seconds = 1.5;
+
+// Audio
+// Mono f32 samples
+const AUDIO_SAMPLE_RATE: u32 = 44100;
+// About 0.37s, many frames' worth in case the host falls behind
+const AUDIO_BUFFER_CAPACITY: usize = 16384;
+const VOICES_CAPACITY: usize = 8;
+const AUDIO_VOLUME: f32 = 0.5;
+// Slides never take a sound below what speakers can play
+const MIN_FREQUENCY: f32 = 20.0;
+
+// Music
+const MUSIC_CHANNELS_CAPACITY: usize = 4;
+const MUSIC_VOLUME: f32 = 0.4;
+const ROWS_PER_BEAT: f32 = 4.0;
+// The gameplay song gets this much faster as the difficulty maxes out
+const MUSIC_DIFFICULTY_SPEEDUP: f32 = 0.25;
+// Steps per second through an arpeggio's three notes
+const ARPEGGIO_RATE: f32 = 50.0;
+const VIBRATO_RATE: f32 = 6.0;
+// Pitch swing per step of a vibrato effect's depth
+const VIBRATO_DEPTH: f32 = 0.003;
+// C4 to B4, other octaves are these doubled or halved
+const NOTE_FREQUENCIES: [f32; 12] = [
+    261.63, 277.18, 293.66, 311.13, 329.63, 349.23, 369.99, 392.00, 415.30, 440.00, 466.16, 493.88,
+];
+const NOTE_C4: i32 = 60;
+// Note bytes below this are not pitches, see songs.rs
+const NOTE_OFF: u8 = 1;
+const NOTE_LOWEST: u8 = 12;
+const EFFECT_ARPEGGIO: u8 = 0x1;
+const EFFECT_VOLUME: u8 = 0x2;
+const EFFECT_VIBRATO: u8 = 0x3;
+// Instruments for the songs, numbered from 1 there
+const INSTRUMENTS: [Instrument; 5] = [
+    // Lead
+    Instrument::new(Waveform::Square, 0.25, Envelope::new(0.01, 0.1, 0.6, 0.0, 0.1), 0.5),
+    // Bass
+    Instrument::new(Waveform::Saw, 0.5, Envelope::new(0.005, 0.05, 0.8, 0.0, 0.05), 0.45),
+    // Pad, usually arpeggiated
+    Instrument::new(Waveform::Square, 0.5, Envelope::new(0.02, 0.2, 0.4, 0.0, 0.2), 0.3),
+    // Kick
+    Instrument::new(Waveform::Noise, 0.5, Envelope::new(0.0, 0.08, 0.0, 0.0, 0.01), 0.8),
+    // Snare and hi-hat
+    Instrument::new(Waveform::Noise, 0.5, Envelope::new(0.0, 0.1, 0.1, 0.0, 0.05), 0.5),
+];
+
+// Sound effects
+const SHOOT_SOUND: SoundEffect = SoundEffect::new(Waveform::Square, 880.0, Envelope::new(0.0, 0.02, 0.5, 0.05, 0.08))
+    .sliding(-2400.0)
+    .duty(0.25)
+    .volume(0.25);
+const ENEMY_DEATH_SOUND: SoundEffect = SoundEffect::new(Waveform::Noise, 1200.0, Envelope::new(0.0, 0.05, 0.6, 0.1, 0.25))
+    .sliding(-2000.0)
+    .volume(0.4);
+const PLAYER_HIT_SOUND: SoundEffect = SoundEffect::new(Waveform::Saw, 220.0, Envelope::new(0.0, 0.05, 0.7, 0.1, 0.2))
+    .sliding(-300.0)
+    .volume(0.4);
+// The classic coin pickup, a note and then a fourth up
+const PICKUP_SOUND: SoundEffect = SoundEffect::new(Waveform::Square, 988.0, Envelope::new(0.0, 0.0, 1.0, 0.15, 0.2))
+    .jumping(0.07, 4.0 / 3.0)
+    .volume(0.25);
+const GAME_OVER_SOUND: SoundEffect = SoundEffect::new(Waveform::Square, 440.0, Envelope::new(0.01, 0.1, 0.6, 0.6, 0.6))
+    .sliding(-250.0)
+    .volume(0.3);
+
+// Title
+const TITLE_TEXT: &[u8] = b"RUST BROWSER GAME";
+const TITLE_COLOR: Pixel = Pixel::rgba(0x00, 0xA2, 0xFF, 0xFF);
+const TITLE_GRADIENT_COLOR: Pixel = Pixel::rgba(0x00, 0x4E, 0xA8, 0xFF);
+const TITLE_OUTLINE_COLOR: Pixel = Pixel::rgba(0xFF, 0xFF, 0xFF, 0xFF);
+const TITLE_WAVE_AMPLITUDE: i32 = 6;
+
+// Wavy text
+const TEXT_WAVE_AMPLITUDE: i32 = 4;
+// Radians per second
+const TEXT_WAVE_SPEED: f32 = 4.0;
+// Radians per font pixel along the line
+const TEXT_WAVE_STEP: f32 = 0.15;
+
 // Font
-const FONT_IMAGE_WIDTH: usize = 128;
-const FONT_IMAGE_HEIGHT: usize = 64;
-const FONT_IMAGE_COLS: usize = 18;
+pub const FONT_IMAGE_WIDTH: usize = 128;
+pub const FONT_IMAGE_HEIGHT: usize = 64;
+pub const FONT_IMAGE_COLS: usize = 18;
 const FONT_IMAGE_ROWS: usize = 7;
-const FONT_CHAR_WIDTH: usize = FONT_IMAGE_WIDTH / FONT_IMAGE_COLS;
-const FONT_CHAR_HEIGHT: usize = FONT_IMAGE_HEIGHT / FONT_IMAGE_ROWS;
+pub const FONT_CHAR_WIDTH: usize = FONT_IMAGE_WIDTH / FONT_IMAGE_COLS;
+pub const FONT_CHAR_HEIGHT: usize = FONT_IMAGE_HEIGHT / FONT_IMAGE_ROWS;
 const BITS_IN_BYTE: usize = 8;
-const COPYRIGHT_TEXT: &[u8] = b"Made by realsanjeev";
+const FONT_FIRST_CODE: u32 = 32;
+const FONT_LAST_CODE: u32 = 126;
+// Latin-1 Supplement, from the no-break space to ÿ
+const FONT_LATIN1_FIRST_CODE: u32 = 0xA0;
+const FONT_LATIN1_LAST_CODE: u32 = 0xFF;
+const FONT_REPLACEMENT_CODE: u32 = b'?' as u32;
+// How many markup tags can be open at once
+const MARKUP_MAX_DEPTH: usize = 8;
+// Stands in for malformed UTF-8, rendered as FONT_REPLACEMENT_CODE
+const UTF8_REPLACEMENT_CHARACTER: u32 = 0xFFFD;
+const FONT_GLYPHS_CAPACITY: usize = 256;
+const FONT_PIXELS_CAPACITY: usize = 64 * 1024;
+const FONT_GLYPH_MAX_WIDTH: usize = 32;
+const FONT_GLYPH_MAX_HEIGHT: usize = 64;
+// Code points below this are mapped straight to glyphs
+const FONT_MAP_SIZE: usize = 256;
+const FONT_LETTER_SPACING: i32 = 1;
+// (left, right, adjustment) in font pixels, applied between the two glyphs
+const KERNING_PAIRS: &[(u8, u8, i32)] = &[
+    (b'T', b'a', -1), (b'T', b'e', -1), (b'T', b'o', -1), (b'T', b'r', -1),
+    (b'T', b'.', -1), (b'T', b',', -1), (b'F', b'.', -1), (b'F', b',', -1),
+    (b'P', b'.', -1), (b'P', b',', -1), (b'L', b'T', -1), (b'L', b'V', -1),
+    (b'L', b'Y', -1), (b'V', b'a', -1), (b'V', b'o', -1), (b'Y', b'o', -1),
+    (b'r', b'.', -1), (b'r', b',', -1), (b'f', b'.', -1),
+];
+const FONT_UPLOAD_CAPACITY: usize = 64 * 1024;
 const COPYRIGHT_SCALE: i32 = 2;
-const COPYRIGHT_PADDING: usize = 10;
-
-const COMPRESSED_FONT: [u8; 622] = [
+const COPYRIGHT_PADDING: i32 = 10;
+
+// Glyph cache
+const GLYPH_CACHE_CAPACITY: usize = 256;
+const GLYPH_CACHE_PIXELS: usize = 128 * 1024;
+const GLYPH_CACHE_SPANS: usize = 16 * 1024;
+
+// PSF2 bitmap fonts
+const PSF2_MAGIC: u32 = 0x864A_B572;
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_START_SEQUENCE: u8 = 0xFE;
+const TITLE_FONT_PSF: &[u8] = include_bytes!("fonts/title.psf");
+const HUD_FONT_PSF: &[u8] = include_bytes!("fonts/hud.psf");
+
+// Generated from fonts/font.pbm by `./host font-encode fonts/font.pbm`
+pub const COMPRESSED_FONT: [u8; 622] = [
     0x00, 0x11, 0x20, 0xa1, 0x41, 0x0c, 0x0e, 0x08, 0x08, 0x40, 0x00, 0x05, 0x38, 0x20, 0x00, 0x01,
     0x20, 0xa1, 0x43, 0xcc, 0x92, 0x08, 0x10, 0x21, 0x50, 0x80, 0x00, 0x02, 0x02, 0x44, 0x60, 0x00,
     0x01, 0x20, 0x03, 0xe5, 0x01, 0x14, 0x00, 0x01, 0x20, 0x10, 0xe0, 0x80, 0x00, 0x02, 0x04, 0x4c,
@@ -104,6 +405,55 @@ const COMPRESSED_FONT: [u8; 622] = [
     0x80, 0x00, 0x0c, 0x20, 0x20, 0x40, 0x80, 0x00, 0x0c, 0x7c, 0x10, 0x41, 0x00, 0xbd,
 ];
 
+// Generated from fonts/latin1.pbm by `./host font-encode fonts/latin1.pbm`
+pub const COMPRESSED_FONT_LATIN1: [u8; 703] = [
+    0x00, 0x08, 0xf8, 0x00, 0x03, 0x1f, 0x3e, 0x00, 0x03, 0x20, 0x40, 0xc0, 0x08, 0x84, 0x1c, 0x45,
+    0x04, 0xc0, 0x00, 0x02, 0x20, 0x80, 0x30, 0x00, 0x03, 0xf1, 0x24, 0x45, 0x04, 0x20, 0x01, 0x34,
+    0x20, 0x90, 0x00, 0x01, 0x2c, 0x80, 0x48, 0x20, 0x00, 0x01, 0x21, 0x41, 0x03, 0x82, 0x04, 0x18,
+    0x01, 0x44, 0xe1, 0x27, 0xc0, 0x2a, 0x80, 0x48, 0x20, 0x00, 0x01, 0x21, 0x43, 0xc2, 0x8f, 0x80,
+    0x24, 0x01, 0x44, 0xe2, 0x40, 0x4f, 0xac, 0x80, 0x30, 0xf8, 0x00, 0x01, 0x21, 0x41, 0x03, 0x82,
+    0x04, 0x18, 0x01, 0x34, 0x01, 0x20, 0x40, 0x2a, 0x80, 0x00, 0x01, 0x20, 0x00, 0x01, 0x20, 0xf1,
+    0x04, 0x4f, 0x84, 0x04, 0x01, 0x05, 0xe0, 0x90, 0x00, 0x01, 0x20, 0x80, 0x00, 0x01, 0x20, 0x00,
+    0x01, 0x20, 0x43, 0xe0, 0x02, 0x04, 0x1c, 0x00, 0x01, 0xf8, 0x00, 0x03, 0x1f, 0x00, 0x02, 0xf8,
+    0x00, 0x18, 0x01, 0x02, 0x0c, 0x00, 0x01, 0x08, 0x04, 0x10, 0x48, 0x30, 0x60, 0x20, 0x03, 0xc0,
+    0x00, 0x01, 0x08, 0x30, 0x03, 0x16, 0x24, 0x42, 0x04, 0x08, 0x28, 0xb0, 0x08, 0x10, 0x40, 0x07,
+    0x40, 0x00, 0x01, 0x18, 0x49, 0x21, 0x22, 0x4c, 0x80, 0x0e, 0x1c, 0x38, 0x70, 0x10, 0x20, 0x02,
+    0x27, 0x40, 0x00, 0x01, 0x08, 0x48, 0x90, 0x40, 0x85, 0x02, 0x11, 0x22, 0x44, 0x88, 0x20, 0x10,
+    0x02, 0x23, 0x42, 0x00, 0x01, 0x1c, 0x30, 0x48, 0xa1, 0x6d, 0x44, 0x1f, 0x3e, 0x7c, 0xf8, 0x38,
+    0x60, 0x02, 0x21, 0x40, 0x00, 0x03, 0x91, 0x62, 0x22, 0xc8, 0x11, 0x22, 0x44, 0x88, 0x00, 0x02,
+    0x03, 0x61, 0x40, 0x00, 0x02, 0x79, 0x22, 0xfc, 0x45, 0xe8, 0x91, 0x22, 0x44, 0x88, 0x00, 0x02,
+    0x02, 0xc1, 0x40, 0x04, 0x00, 0x03, 0x20, 0x68, 0x47, 0x11, 0x22, 0x44, 0x88, 0x00, 0x02, 0x02,
+    0x00, 0x02, 0x0c, 0x00, 0x0a, 0x44, 0x70, 0x00, 0x01, 0x02, 0x01, 0x04, 0x22, 0x20, 0x10, 0x42,
+    0x20, 0x04, 0x88, 0x04, 0x10, 0x48, 0x00, 0x01, 0x50, 0xf1, 0xc1, 0x02, 0x0a, 0x00, 0x01, 0x10,
+    0x20, 0xa0, 0x07, 0x8b, 0x04, 0x08, 0x28, 0xb0, 0x38, 0x71, 0x42, 0x27, 0xcf, 0x9f, 0x3e, 0x7c,
+    0xf9, 0xf3, 0xe4, 0x48, 0x8e, 0x1c, 0x38, 0x70, 0x44, 0x89, 0x42, 0x04, 0x08, 0x10, 0x20, 0x10,
+    0x20, 0x40, 0x84, 0x4c, 0x91, 0x22, 0x44, 0x88, 0x7c, 0xf9, 0xe2, 0x07, 0x8f, 0x1e, 0x3c, 0x10,
+    0x20, 0x40, 0x8e, 0x4a, 0x91, 0x22, 0x44, 0x88, 0x44, 0x89, 0x42, 0x04, 0x08, 0x10, 0x20, 0x10,
+    0x20, 0x40, 0x84, 0x49, 0x91, 0x22, 0x44, 0x88, 0x44, 0x89, 0x42, 0x24, 0x08, 0x10, 0x20, 0x10,
+    0x20, 0x40, 0x84, 0x48, 0x91, 0x22, 0x44, 0x88, 0x44, 0x89, 0x71, 0xc7, 0xcf, 0x9f, 0x3e, 0x7c,
+    0xf9, 0xf3, 0xe7, 0x88, 0x8e, 0x1c, 0x38, 0x70, 0x00, 0x02, 0x01, 0x80, 0x00, 0x0c, 0x44, 0x00,
+    0x01, 0x01, 0x00, 0x01, 0x82, 0x11, 0x04, 0x00, 0x06, 0x08, 0x00, 0x04, 0xf0, 0x81, 0x05, 0x00,
+    0x01, 0x08, 0x40, 0x60, 0x80, 0x41, 0x04, 0x91, 0x14, 0x00, 0x02, 0x38, 0x89, 0x32, 0x24, 0x48,
+    0x91, 0x22, 0x78, 0x90, 0x40, 0x82, 0x8b, 0x00, 0x01, 0x08, 0x00, 0x02, 0x44, 0x51, 0x52, 0x24,
+    0x48, 0x91, 0x22, 0x44, 0x90, 0xe1, 0xc3, 0x87, 0x0e, 0x1c, 0x68, 0x70, 0x44, 0x21, 0x52, 0x24,
+    0x48, 0x91, 0x14, 0x44, 0xb0, 0x10, 0x20, 0x40, 0x81, 0x02, 0x14, 0x88, 0x44, 0x51, 0x52, 0x24,
+    0x48, 0x91, 0x08, 0x78, 0x88, 0xf1, 0xe3, 0xc7, 0x8f, 0x1e, 0x7c, 0x80, 0x44, 0x89, 0x92, 0x24,
+    0x48, 0x91, 0x08, 0x40, 0x89, 0x12, 0x24, 0x48, 0x91, 0x22, 0x90, 0x88, 0x38, 0x01, 0xe1, 0xc3,
+    0x87, 0x0e, 0x08, 0x40, 0xb0, 0xf1, 0xe3, 0xc7, 0x8f, 0x1e, 0x6c, 0x70, 0x00, 0x0f, 0x60, 0x00,
+    0x10, 0x20, 0x10, 0x42, 0x22, 0x01, 0x04, 0x22, 0x28, 0x48, 0x80, 0x41, 0x04, 0x91, 0x00, 0x02,
+    0x40, 0x10, 0x20, 0xa0, 0x01, 0x02, 0x0a, 0x00, 0x01, 0x10, 0xb0, 0x40, 0x82, 0x8b, 0x00, 0x01,
+    0x08, 0x00, 0x01, 0x20, 0x38, 0x70, 0xe1, 0xc7, 0x0e, 0x1c, 0x38, 0x28, 0xf0, 0xe1, 0xc3, 0x87,
+    0x0e, 0x00, 0x01, 0x3a, 0x88, 0x44, 0x89, 0x12, 0x21, 0x02, 0x04, 0x08, 0x08, 0x89, 0x12, 0x24,
+    0x48, 0x91, 0x3e, 0x4c, 0x88, 0x7c, 0xf9, 0xf3, 0xe1, 0x02, 0x04, 0x08, 0x3c, 0x89, 0x12, 0x24,
+    0x48, 0x91, 0x00, 0x01, 0x54, 0x88, 0x40, 0x81, 0x02, 0x01, 0x02, 0x04, 0x08, 0x44, 0x89, 0x12,
+    0x24, 0x48, 0x91, 0x08, 0x64, 0x88, 0x3c, 0x78, 0xf1, 0xe7, 0xcf, 0x9f, 0x3e, 0x38, 0x88, 0xe1,
+    0xc3, 0x87, 0x0e, 0x00, 0x01, 0xb8, 0x78, 0x00, 0x20, 0x08, 0x21, 0x10, 0x44, 0x08, 0x80, 0x00,
+    0x0a, 0x10, 0x50, 0x00, 0x01, 0x84, 0x00, 0x0c, 0x44, 0x89, 0x12, 0x27, 0x88, 0x80, 0x00, 0x0a,
+    0x44, 0x89, 0x12, 0x24, 0x48, 0x80, 0x00, 0x0a, 0x44, 0x89, 0x11, 0xe4, 0x47, 0x80, 0x00, 0x0a,
+    0x44, 0x89, 0x10, 0x27, 0x80, 0x80, 0x00, 0x0a, 0x3c, 0x78, 0xf1, 0xc4, 0x07, 0x00, 0xbb,
+];
+
+
 // RNG (Random Number Generator)
 const RNG_A: i32 = 1103515245;
 const RNG_C: i32 = 12345;
@@ -123,524 +473,3348 @@ impl Rng {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 #[repr(C)]
 struct Pixel(u32);
 
 impl Pixel {
+    const TRANSPARENT: Self = Self(0);
+
+    // Spelled out byte by byte, shifts by 0 included
+    #[allow(clippy::identity_op, clippy::erasing_op)]
     const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
         Self(((a as u32) << (3*8)) |
              ((b as u32) << (2*8)) |
              ((g as u32) << (1*8)) |
              ((r as u32) << (0*8)))
     }
-}
 
-#[repr(C)]
-pub struct Display {
-    pixels: [Pixel; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+    // Linearly interpolates every channel towards `that` by `alpha` (0..=255)
+    const fn mix(self, that: Self, alpha: u32) -> Self {
+        let mut result = 0;
+        let mut shift = 0;
+        while shift < 32 {
+            let a = (self.0 >> shift) & 0xFF;
+            let b = (that.0 >> shift) & 0xFF;
+            let c = (a * (255 - alpha) + b * alpha) / 255;
+            result |= c << shift;
+            shift += 8;
+        }
+        Self(result)
+    }
 }
 
-// Utility function
-const fn max(x: i32, y: i32) -> i32 { if x > y { x } else { y } }
-const fn min(x: i32, y: i32) -> i32 { if x < y { x } else { y } }
-const fn clamp(x: i32, low: i32, high: i32) -> i32 { min(max(low, x), high) }
-
+// Fill of a glyph: a vertical gradient from top to bottom, a solid colour
+// when both are the same
+#[derive(Clone, Copy, PartialEq)]
+struct Paint {
+    top: Pixel,
+    bottom: Pixel,
+}
 
-impl Display {
-    fn fill(&mut self, pixel: Pixel) {
-        for pixel_ref in self.pixels.iter_mut() {
-            *pixel_ref = pixel;
-        }
+impl Paint {
+    const fn solid(color: Pixel) -> Self {
+        Self { top: color, bottom: color }
     }
 
-    fn fill_rect(&mut self, x0: i32, y0: i32, w: i32, h: i32, pixel: Pixel) {
-        let x1 = clamp(x0,         0, (DISPLAY_WIDTH - 1)  as i32) as usize;
-        let x2 = clamp(x0 + w - 1, 0, (DISPLAY_WIDTH - 1)  as i32) as usize;
-        let y1 = clamp(y0,         0, (DISPLAY_HEIGHT - 1) as i32) as usize;
-        let y2 = clamp(y0 + h - 1, 0, (DISPLAY_HEIGHT - 1) as i32) as usize;
-
-        for y in y1..=y2 {
-            for x in x1..=x2 {
-                if let Some(pixel_ref) = self.pixels.get_mut(y * DISPLAY_WIDTH + x) {
-                    *pixel_ref = pixel
-                }
-            }
+    // Colour of `row` out of `rows`
+    fn at(&self, row: i32, rows: i32) -> Pixel {
+        if self.top == self.bottom || rows <= 1 {
+            return self.top;
         }
+        self.top.mix(self.bottom, (row * 255 / (rows - 1)) as u32)
     }
 }
 
-type Seconds = f32;
+// Utility function
+const fn max(x: i32, y: i32) -> i32 { if x > y { x } else { y } }
+const fn min(x: i32, y: i32) -> i32 { if x < y { x } else { y } }
+const fn clamp(x: i32, low: i32, high: i32) -> i32 { min(max(low, x), high) }
 
 #[derive(Clone, Copy)]
 #[repr(C)]
-struct Entity {
+pub struct Rect {
     x: i32,
     y: i32,
-    alive: bool,
+    w: i32,
+    h: i32,
 }
 
-impl Entity {
-    const fn new(x: i32, y: i32) -> Self {
-        Self { x, y, alive: true }
+impl Rect {
+    const fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Self { x, y, w, h }
     }
 
-    const fn dead() -> Self {
-        Self {
-            x: 0,
-            y: 0,
-            alive: false,
-        }
+    const fn screen() -> Self {
+        Self::new(0, 0, DISPLAY_WIDTH as i32, DISPLAY_HEIGHT as i32)
     }
 
-    fn revive(&mut self, x: i32, y: i32) {
-        self.alive = true;
-        self.x = x;
-        self.y = y;
+    // Clips the rectangle to the display, None if nothing is left
+    fn clipped(x0: i32, y0: i32, w: i32, h: i32) -> Option<Self> {
+        let x1 = max(x0, 0);
+        let y1 = max(y0, 0);
+        let x2 = min(x0 + w, DISPLAY_WIDTH as i32);
+        let y2 = min(y0 + h, DISPLAY_HEIGHT as i32);
+        if x1 < x2 && y1 < y2 {
+            Some(Self::new(x1, y1, x2 - x1, y2 - y1))
+        } else {
+            None
+        }
     }
 
-    fn render(&self, display: &mut Display, size: i32, color: Pixel) {
-        if self.alive {
-            let x = self.x - size / 2;
-            let y = self.y - size / 2;
-            display.fill_rect(x + SHADOW_OFFSET, y + SHADOW_OFFSET, size, size, SHADOW_COLOR);
-            display.fill_rect(x, y, size, size, color);
-        }
+    fn union(&self, that: &Self) -> Self {
+        let x1 = min(self.x, that.x);
+        let y1 = min(self.y, that.y);
+        let x2 = max(self.x + self.w, that.x + that.w);
+        let y2 = max(self.y + self.h, that.y + that.h);
+        Self::new(x1, y1, x2 - x1, y2 - y1)
     }
 
-    fn overlaps(&self, self_size: i32, that: &Self, that_size: i32) -> bool {
-        if !self.alive || !that.alive {
-            return false;
-        }
+    // Shrunk by `padding` on every side
+    const fn inset(&self, padding: i32) -> Self {
+        Self::new(self.x + padding, self.y + padding, self.w - padding * 2, self.h - padding * 2)
+    }
 
-        let left1 = self.x - self_size / 2;
-        let right1 = self.x + self_size / 2;
-        let top1 = self.y - self_size / 2;
-        let bottom1 = self.y + self_size / 2;
+    // What is left after cutting `height` pixels off the top
+    const fn below(&self, height: i32) -> Self {
+        Self::new(self.x, self.y + height, self.w, max(self.h - height, 0))
+    }
 
-        let left2 = that.x - that_size / 2;
-        let right2 = that.x + that_size / 2;
-        let top2 = that.y - that_size / 2;
-        let bottom2 = that.y + that_size / 2;
+    fn contains(&self, x: i32, y: i32) -> bool {
+        self.x <= x && x < self.x + self.w && self.y <= y && y < self.y + self.h
+    }
 
-        right1 >= left2 && right2 >= left1 && bottom2 >= top1 && bottom1 >= top2
+    // Overlapping or separated by at most `distance` pixels
+    fn near(&self, that: &Self, distance: i32) -> bool {
+        self.x - distance <= that.x + that.w && that.x - distance <= self.x + self.w &&
+        self.y - distance <= that.y + that.h && that.y - distance <= self.y + self.h
     }
 }
 
-struct Font {
-    pixels: [u8; FONT_IMAGE_WIDTH * FONT_IMAGE_HEIGHT],
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct RectList {
+    rects: [Rect; DIRTY_RECTS_CAPACITY],
+    count: usize,
 }
 
-impl Font {
-    fn decompress_from_bytes(&mut self, bytes: &[u8]) {
-        let n = bytes.len();
-        let mut i = 0;
-        let mut pixels_size: usize = 0;
-        while i < n {
-            if let Some(byte) = bytes.get(i).cloned() {
-                if byte == 0x00 {
-                    i += 1;
-                    if let Some(next_byte) = bytes.get(i).cloned() {
-                        pixels_size += next_byte as usize * 8;
-                    } else {
-                        break;
-                    }
-                    i += 1;
-                } else {
-                    for bit_index in 0..BITS_IN_BYTE {
-                        if pixels_size < self.pixels.len() {
-                            if let Some(pixel_ref) = self.pixels.get_mut(pixels_size) {
-                                *pixel_ref = ((byte >> (BITS_IN_BYTE - bit_index - 1)) & 1) * 0xFF;
-                            }
-                            pixels_size += 1;
-                        } else {
-                            break;
-                        }
-                    }
-                    i += 1;
-                }
-            } else {
-                break;
-            }
+impl RectList {
+    const fn empty() -> Self {
+        Self {
+            rects: [Rect::new(0, 0, 0, 0); DIRTY_RECTS_CAPACITY],
+            count: 0,
         }
     }
 
-    fn get(&self, x: i32, y: i32) -> Option<&u8> {
-        if x >= 0 && x < FONT_IMAGE_WIDTH as i32 && y >= 0 && y < FONT_IMAGE_HEIGHT as i32 {
-            self.pixels.get(y as usize * FONT_IMAGE_WIDTH + x as usize)
-        } else {
-            None
-        }
+    fn clear(&mut self) {
+        self.count = 0;
     }
 
-    fn render_ascii(&self,
-                    display: &mut Display,
-                    code: u8,
-                    start_x: i32, start_y: i32,
-                    scale: i32,
-                    color: Pixel) {
-        if 32 <= code && code <= 126 {
-            let char_x = (code - 32) as usize % FONT_IMAGE_COLS;
-            let char_y = (code - 32) as usize / FONT_IMAGE_COLS;
-
-            for y in 0..FONT_CHAR_HEIGHT as i32 {
-                for x in 0..FONT_CHAR_WIDTH as i32 {
-                    let font_x = char_x as i32 * FONT_CHAR_WIDTH as i32 + x;
-                    let font_y = char_y as i32 * FONT_CHAR_HEIGHT as i32 + y;
-
-                    if let Some(alpha) = self.get(font_x, font_y) {
-                        if *alpha == 0xFF {
-                            let display_start_x = start_x + x * scale;
-                            let display_start_y = start_y + y * scale;
-                            display.fill_rect(display_start_x, display_start_y, scale, scale, color);
-                        }
-                    }
+    fn push(&mut self, rect: Rect) {
+        // Nearby rectangles are merged, so a line of text or an entity with its
+        // shadow ends up as a single rectangle
+        if let Some(rects) = self.rects.get_mut(0..self.count) {
+            for existing in rects.iter_mut() {
+                if existing.near(&rect, DIRTY_RECT_MERGE_DISTANCE) {
+                    *existing = existing.union(&rect);
+                    return;
                 }
             }
+        }
+
+        if let Some(slot) = self.rects.get_mut(self.count) {
+            *slot = rect;
+            self.count += 1;
         } else {
-            // Render '?' for unknown characters
-            let char_x = ('?' as u8 - 32) as usize % FONT_IMAGE_COLS;
-            let char_y = ('?' as u8 - 32) as usize / FONT_IMAGE_COLS;
-            for y in 0..FONT_CHAR_HEIGHT as i32 {
-                for x in 0..FONT_CHAR_WIDTH as i32 {
-                    let font_x = char_x as i32 * FONT_CHAR_WIDTH as i32 + x;
-                    let font_y = char_y as i32 * FONT_CHAR_HEIGHT as i32 + y;
-                    if let Some(alpha) = self.get(font_x, font_y) {
-                        if *alpha == 0xFF {
-                            let display_start_x = start_x + x * scale;
-                            let display_start_y = start_y + y * scale;
-                            display.fill_rect(display_start_x, display_start_y, scale, scale, color);
-                        }
-                    }
-                }
+            // Out of slots, collapse everything into a bounding box
+            log!(LogLevel::Debug, "dirty rects: out of {} slots, merging them", DIRTY_RECTS_CAPACITY);
+            let mut bounds = rect;
+            for existing in self.rects.iter() {
+                bounds = bounds.union(existing);
             }
+            *self = Self::empty();
+            self.push(bounds);
         }
     }
+}
 
-    fn render_bytes(&self,
-                    display: &mut Display,
-                    bytes: &[u8],
-                    x: i32, y: i32,
-                    scale: i32,
-                    color: Pixel) {
-        for (i, byte) in bytes.iter().enumerate() {
-            self.render_ascii(
-                display,
-                *byte,
-                x + i as i32 * FONT_CHAR_WIDTH as i32 * scale, y,
-                scale,
-                color);
-        }
-    }
+#[repr(C)]
+pub struct Display {
+    pixels: [Pixel; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+    // Everything that changed since the previous frame, read by the host
+    dirty: RectList,
+    // Everything drawn this frame, cleared at the start of the next one
+    drawn: RectList,
+    // Whether the whole display has been painted at least once
+    cleared: bool,
+}
 
-    fn render_bytes_shadowed(&self, display: &mut Display, bytes: &[u8], x: i32, y: i32, scale: i32, color: Pixel, shadow_color: Pixel, shadow_offset: i32 ) {
-        // Draw shadow first
-        self.render_bytes(display, bytes, x + shadow_offset, y + shadow_offset, scale, shadow_color);
-        // Draw text on top
-        self.render_bytes(display, bytes, x, y, scale, color);
+impl Display {
+    // Restores the background under whatever was drawn in the previous frame
+    // instead of refilling the whole display
+    fn begin_frame(&mut self, background: Pixel) {
+        if !self.cleared {
+            self.drawn.push(Rect::screen());
+            self.cleared = true;
+        }
+        self.dirty = self.drawn;
+        self.drawn.clear();
+        for i in 0..self.dirty.count {
+            if let Some(rect) = self.dirty.rects.get(i).cloned() {
+                self.paint_rect(rect, background);
+            }
+        }
     }
 
-    fn text_width(&self, text: &[u8], scale: i32) -> i32 {
-        text.len() as i32 * FONT_CHAR_WIDTH as i32 * scale
+    fn mark(&mut self, rect: Rect) {
+        self.dirty.push(rect);
+        self.drawn.push(rect);
     }
 
-    fn text_height(&self, scale: i32) -> i32 {
-        FONT_CHAR_HEIGHT as i32 * scale
+    // Row `y` of an already clipped rectangle
+    fn row_mut(&mut self, rect: &Rect, y: i32) -> &mut [Pixel] {
+        let start = y as usize * DISPLAY_WIDTH + rect.x as usize;
+        self.pixels.get_mut(start..start + rect.w as usize).unwrap_or(&mut [])
     }
-}
-
-const LABEL_CAPACITY: usize = 64;
-struct Label {
-    chars: [u8; LABEL_CAPACITY],
-    count: usize,
-}
 
-impl Label {
-    const fn empty() -> Self {
-        Self {
-            chars: [0; LABEL_CAPACITY],
-            count: 0,
+    fn paint_rect(&mut self, rect: Rect, pixel: Pixel) {
+        for y in rect.y..rect.y + rect.h {
+            self.row_mut(&rect, y).fill(pixel);
         }
     }
 
-    fn render(&self,
-              display: &mut Display,
-              font: &Font,
-              x: i32, y: i32,
-              scale: i32,
-              color: Pixel) {
-        if let Some(bytes) = self.chars.get(0..self.count) {
-            font.render_bytes_shadowed(display, bytes, x, y, scale, color, SHADOW_COLOR, SHADOW_OFFSET);
+    fn fill_rect(&mut self, x0: i32, y0: i32, w: i32, h: i32, pixel: Pixel) {
+        if let Some(rect) = Rect::clipped(x0, y0, w, h) {
+            self.paint_rect(rect, pixel);
+            self.mark(rect);
         }
     }
 
-    fn clear(&mut self) {
-        self.count = 0;
-    }
-
-    fn push_byte(&mut self, b: u8) {
-        if self.count < LABEL_CAPACITY {
-            if let Some(char_ref) = self.chars.get_mut(self.count) {
-                *char_ref = b;
-                self.count += 1;
+    // Copies the opaque spans of a w×h bitmap, one slice copy per span
+    fn blit(&mut self, x0: i32, y0: i32, w: i32, h: i32, bitmap: &[Pixel], spans: &[Span]) {
+        if let Some(rect) = Rect::clipped(x0, y0, w, h) {
+            for span in spans.iter() {
+                let y = y0 + span.y as i32;
+                let x1 = max(x0 + span.x as i32, rect.x);
+                let x2 = min(x0 + span.x as i32 + span.len as i32, rect.x + rect.w);
+                if y < rect.y || y >= rect.y + rect.h || x1 >= x2 {
+                    continue;
+                }
+                let len = (x2 - x1) as usize;
+                let src_start = span.y as usize * w as usize + (x1 - x0) as usize;
+                let dst_start = y as usize * DISPLAY_WIDTH + x1 as usize;
+                if let (Some(dst), Some(src)) = (self.pixels.get_mut(dst_start..dst_start + len),
+                                                 bitmap.get(src_start..src_start + len)) {
+                    dst.copy_from_slice(src);
+                }
             }
+            self.mark(rect);
         }
     }
 
-    fn push_bytes(&mut self, bs: &[u8]) {
-        for b in bs {
-            self.push_byte(*b);
+    fn blend_rect(&mut self, x0: i32, y0: i32, w: i32, h: i32, pixel: Pixel, alpha: u32) {
+        if let Some(rect) = Rect::clipped(x0, y0, w, h) {
+            for y in rect.y..rect.y + rect.h {
+                for pixel_ref in self.row_mut(&rect, y).iter_mut() {
+                    *pixel_ref = pixel_ref.mix(pixel, alpha);
+                }
+            }
+            self.mark(rect);
         }
     }
+}
 
-    fn push_int(&mut self, mut x: i32) {
-        let saved_count = self.count;
+// Offset applied to everything drawn in world space. HUD is drawn without it.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Camera {
+    x: i32,
+    y: i32,
+}
 
-        if x == 0 {
-            self.push_byte(b'0');
-        } else {
-            while x > 0 && self.count < LABEL_CAPACITY {
-                self.push_byte((x % 10) as u8 + b'0');
-                x /= 10;
-            }
+impl Camera {
+    const fn origin() -> Self {
+        Self { x: 0, y: 0 }
+    }
+}
 
-            if x > 0 {
-                // x does not fit into the Label rolling back and quitting
-                self.count = saved_count;
-                return;
-            }
-        }
+type Seconds = f32;
 
-        if let Some(chars) = self.chars.get_mut(saved_count .. self.count) {
-            chars.reverse();
-        }
-    }
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(C)]
+enum Screen {
+    Title,
+    Playing,
+    GameOver,
+    Controls,
+    // Between two levels, the game carries on from where it was
+    Level,
 }
 
+#[derive(Clone, Copy, PartialEq)]
 #[repr(C)]
-pub struct State {
-    player: Entity,
-    player_health: i32,
-    bullets: [Entity; BULLETS_CAPACITY],
-    enemies: [Entity; ENEMIES_CAPACITY],
-    enemy_spawn_cooldown: Seconds,
-    pause: bool,
-    game_over: bool,
-    score: usize,
-    score_label: Label,
-    health_label: Label,
-    rng: Rng,
+enum TransitionKind {
+    Fade,
+    Wipe,
+    Dissolve,
 }
 
-impl State {
-    const fn default() -> Self {
+// Covers the screen during the first half, switches to `target` in the
+// middle and uncovers it during the second half.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Transition {
+    kind: TransitionKind,
+    target: Screen,
+    time: Seconds,
+    active: bool,
+}
+
+impl Transition {
+    const fn none() -> Self {
         Self {
-            player: Entity::new(DISPLAY_WIDTH as i32 / 2, DISPLAY_HEIGHT as i32 - PLAYER_SIZE),
-            player_health: PLAYER_INITIAL_HEALTH,
-            bullets: [Entity::dead(); BULLETS_CAPACITY],
-            enemies: [Entity::dead(); ENEMIES_CAPACITY],
-            enemy_spawn_cooldown: ENEMY_INITIAL_SPAWN_PERIOD,
-            pause: false,
-            game_over: false,
-            score: 0,
-            score_label: Label::empty(),
-            health_label: Label::empty(),
-            rng: Rng::from_seed(123456789),
+            kind: TransitionKind::Fade,
+            target: Screen::Title,
+            time: 0.0,
+            active: false,
         }
     }
 
-    fn update(&mut self, dt: Seconds) {
-        if self.pause || self.game_over {
-            return;
+    fn start(&mut self, kind: TransitionKind, target: Screen) {
+        self.kind = kind;
+        self.target = target;
+        self.time = 0.0;
+        self.active = true;
+    }
+
+    // Advances the transition and returns the screen to switch to once the
+    // halfway point is crossed
+    fn update(&mut self, dt: Seconds) -> Option<Screen> {
+        if !self.active {
+            return None;
         }
-        // Update bullets
-        for bullet in self.bullets.iter_mut() {
-            if bullet.alive {
-                bullet.y -= (BULLET_SPEED as f32 * dt) as i32;
-                if bullet.y + BULLET_SIZE / 2 < 0 {
-                    bullet.alive = false;
-                }
-            }
+        let half = TRANSITION_DURATION / 2.0;
+        let before = self.time;
+        self.time += dt;
+        if self.time >= TRANSITION_DURATION {
+            self.active = false;
         }
+        if before < half && self.time >= half {
+            Some(self.target)
+        } else {
+            None
+        }
+    }
 
-        for enemy in self.enemies.iter_mut() {
-            if enemy.alive {
-                {
-                    enemy.y += (ENEMY_SPEED as f32 * dt) as i32;
-                    if enemy.y - ENEMY_SIZE / 2 > DISPLAY_HEIGHT as i32 {
-                        enemy.alive = false;
-                        continue;
-                    }
-                }
+    // 0.0 means fully uncovered, 1.0 means fully covered
+    fn coverage(&self) -> f32 {
+        let t = (self.time / TRANSITION_DURATION).min(1.0);
+        1.0 - (t * 2.0 - 1.0).abs()
+    }
 
-                // Check bullet-enemy collision
-                for bullet in self.bullets.iter_mut() {
-                    if bullet.alive {
-                        if enemy.overlaps(ENEMY_SIZE, bullet, BULLET_SIZE) {
-                            bullet.alive = false;
-                            enemy.alive = false;
-                            self.score += PLAYER_KILL_REWARD;
-                            break;
-                        }
-                    }
+    fn render(&self, display: &mut Display) {
+        if !self.active {
+            return;
+        }
+        let coverage = self.coverage();
+        let w = DISPLAY_WIDTH as i32;
+        let h = DISPLAY_HEIGHT as i32;
+        match self.kind {
+            TransitionKind::Fade => {
+                display.blend_rect(0, 0, w, h, TRANSITION_COLOR, (coverage * 255.0) as u32);
+            }
+            TransitionKind::Wipe => {
+                // Sweeps in from the left, then keeps going off to the right
+                let covered = (coverage * w as f32) as i32;
+                if self.time < TRANSITION_DURATION / 2.0 {
+                    display.fill_rect(0, 0, covered, h, TRANSITION_COLOR);
+                } else {
+                    display.fill_rect(w - covered, 0, covered, h, TRANSITION_COLOR);
                 }
             }
-            // Check player-enemy collision (only if enemy is still alive)
-            if enemy.alive && enemy.overlaps(ENEMY_SIZE, &self.player, PLAYER_SIZE) {
-                enemy.alive = false;
-                self.player_health -= 1;
-                if self.player_health <= 0 {
-                    self.player.alive = false;
-                    self.game_over = true;
+            TransitionKind::Dissolve => {
+                let threshold = (coverage * 256.0) as i32;
+                let cols = (w + DISSOLVE_BLOCK_SIZE - 1) / DISSOLVE_BLOCK_SIZE;
+                let rows = (h + DISSOLVE_BLOCK_SIZE - 1) / DISSOLVE_BLOCK_SIZE;
+                for row in 0..rows {
+                    for col in 0..cols {
+                        // Fixed per-block noise so blocks appear in a stable order
+                        let mut rng = Rng::from_seed(row * cols + col);
+                        rng.rand();
+                        if (rng.rand() >> 16) & 0xFF < threshold {
+                            display.fill_rect(col * DISSOLVE_BLOCK_SIZE, row * DISSOLVE_BLOCK_SIZE,
+                                              DISSOLVE_BLOCK_SIZE, DISSOLVE_BLOCK_SIZE,
+                                              TRANSITION_COLOR);
+                        }
+                    }
                 }
             }
         }
+    }
+}
 
-        self.enemy_spawn_cooldown -= dt;
-        if self.enemy_spawn_cooldown <= 0.0 {
-            let enemy_x = self.rng.rand().abs() % (DISPLAY_WIDTH as i32 - ENEMY_SIZE) + ENEMY_SIZE / 2;
-            self.spawn_enemy(enemy_x, -ENEMY_SIZE / 2);
-            let score_factor = (self.score as f32 * ENEMY_SPAWN_PERIOD_SCORE_FACTOR).min(1.0);
-            let new_cooldown = ENEMY_INITIAL_SPAWN_PERIOD * (1.0 - score_factor * 0.8);
-            self.enemy_spawn_cooldown = new_cooldown.max(ENEMY_MIN_SPAWN_PERIOD);
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u32)]
+enum InputKind {
+    // x, y
+    MouseMove = 1,
+    // code is the button, x, y
+    MouseDown = 2,
+    MouseUp = 3,
+    // code is the key
+    KeyDown = 4,
+    KeyUp = 5,
+    // code tells touches apart, x, y
+    TouchStart = 6,
+    TouchMove = 7,
+    TouchEnd = 8,
+    // code is the axis, x its position from -1 to 1
+    GamepadAxis = 9,
+    // code is the button, x how far it's pressed from 0 to 1
+    GamepadButtonDown = 10,
+    GamepadButtonUp = 11,
+}
+
+impl InputKind {
+    const fn from_u32(kind: u32) -> Option<Self> {
+        match kind {
+            1 => Some(InputKind::MouseMove),
+            2 => Some(InputKind::MouseDown),
+            3 => Some(InputKind::MouseUp),
+            4 => Some(InputKind::KeyDown),
+            5 => Some(InputKind::KeyUp),
+            6 => Some(InputKind::TouchStart),
+            7 => Some(InputKind::TouchMove),
+            8 => Some(InputKind::TouchEnd),
+            9 => Some(InputKind::GamepadAxis),
+            10 => Some(InputKind::GamepadButtonDown),
+            11 => Some(InputKind::GamepadButtonUp),
+            _ => None,
         }
+    }
+}
 
-        self.score_label.clear();
-        self.score_label.push_bytes(b"Score: ");
-        self.score_label.push_int(self.score as i32);
+// Written by the host, so `kind` stays a plain number until it's checked
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct InputEvent {
+    kind: u32,
+    // Host clock in milliseconds, wrapping
+    time: u32,
+    code: i32,
+    x: f32,
+    y: f32,
+}
 
-        self.health_label.clear();
-        self.health_label.push_bytes(b"Health: ");
-        self.health_label.push_int(self.player_health.max(0));
-    }
+// Ring buffer shared with the host. The host writes an event at
+// `write % INPUT_QUEUE_CAPACITY` and then bumps `write`, unless the queue is
+// full. The game bumps `read` as it handles them. Both only ever grow, and
+// wrap around at u32::MAX.
+#[repr(C)]
+pub struct InputQueue {
+    write: u32,
+    read: u32,
+    events: [InputEvent; INPUT_QUEUE_CAPACITY],
+}
 
-    fn render(&self, display: &mut Display, font: &Font) {
-        // Always clear the background
-        display.fill(DISPLAY_BACKGROUND);
-        if !self.pause {
-            self.player.render(display, PLAYER_SIZE, PLAYER_COLOR);
-            for bullet in self.bullets.iter() {
-                bullet.render(display, BULLET_SIZE, BULLET_COLOR)
-            }
-            for enemy in self.enemies.iter() {
-                enemy.render(display, ENEMY_SIZE, ENEMY_COLOR)
-            }
-            self.score_label.render(display, font,
-                                    SCORE_LABEL_X, SCORE_LABEL_Y,
-                                    TEXT_SCALE, SCORE_LABEL_COLOR);
-            // Render health only if player is alive or game just ended
-            if self.player.alive || self.game_over {
-                self.health_label.render(display, font,
-                                        HEALTH_LABEL_X, HEALTH_LABEL_Y,
-                                        TEXT_SCALE, HEALTH_LABEL_COLOR);
-            }
-            {
-                let text = COPYRIGHT_TEXT;
-                let scale = COPYRIGHT_SCALE;
-                let text_w = font.text_width(text, scale);
-                let text_h = font.text_height(scale);
-                let x = DISPLAY_WIDTH as i32 - text_w - COPYRIGHT_PADDING as i32;
-                let y = DISPLAY_HEIGHT as i32 - text_h - COPYRIGHT_PADDING as i32;
-                font.render_bytes_shadowed(display, text, x, y, scale, SCORE_LABEL_COLOR, SHADOW_COLOR, SHADOW_OFFSET / 2);
-            }
-        }
-
-        if self.pause {
-            let text = b"PAUSED";
-            let scale = MESSAGE_SCALE;
-            let text_w = font.text_width(text, scale);
-            let text_h = font.text_height(scale);
-            let x = (DISPLAY_WIDTH as i32 - text_w) / 2;
-            let y = (DISPLAY_HEIGHT as i32 - text_h) / 2;
-            font.render_bytes_shadowed(display, text, x, y, scale, MESSAGE_COLOR, SHADOW_COLOR, SHADOW_OFFSET);
-        }
-
-        if self.game_over {
-            let game_over_text = b"GAME_OVER";
-            let scale = MESSAGE_SCALE;
-            let text_w = font.text_width(game_over_text, scale);
-            let text_h = font.text_height(scale);
-            let game_over_x = (DISPLAY_WIDTH as i32 - text_w) / 2;
-            let game_over_y = (DISPLAY_HEIGHT as i32 - text_h) / 2 - text_h / 2;
-            font.render_bytes_shadowed(display, game_over_text, game_over_x, game_over_y, scale, MESSAGE_COLOR, SHADOW_COLOR, SHADOW_OFFSET);
-
-            let score_text = self.score_label.chars.get(0..self.score_label.count).unwrap_or(b"");
-            let score_scale = TEXT_SCALE;
-            let score_text_w = font.text_width(score_text, score_scale);
-            let score_x = (DISPLAY_WIDTH as i32 - score_text_w) / 2;
-            let score_y = game_over_y + text_h + SCORE_LABEL_PADDING;
-            self.score_label.render(display, font, score_x, score_y, score_scale, SCORE_LABEL_COLOR);
-
-
-            let restart_text = b"Press Space to Restart";
-            let restart_scale = TEXT_SCALE;
-            let restart_text_w = font.text_width(restart_text, restart_scale);
-            let _restart_text_h = font.text_height(restart_scale);
-            let restart_x = (DISPLAY_WIDTH as i32 - restart_text_w) / 2;
-            let restart_y = score_y + font.text_height(score_scale) + SCORE_LABEL_PADDING;
-            font.render_bytes_shadowed(display, restart_text, restart_x, restart_y, restart_scale, MESSAGE_COLOR, SHADOW_COLOR, SHADOW_OFFSET);
+impl InputQueue {
+    fn pop(&mut self) -> Option<InputEvent> {
+        if self.read == self.write {
+            return None;
+        }
+        // Only a broken host gets further ahead than the queue holds, and only
+        // the newest events are left by then
+        let queued = self.write.wrapping_sub(self.read);
+        if queued as usize > INPUT_QUEUE_CAPACITY {
+            log!(LogLevel::Warn, "input: {} events queued, more than the {} that fit, skipping the oldest", queued, INPUT_QUEUE_CAPACITY);
+            self.read = self.write.wrapping_sub(INPUT_QUEUE_CAPACITY as u32);
         }
+        let event = self.events.get(self.read as usize % INPUT_QUEUE_CAPACITY).copied();
+        self.read = self.read.wrapping_add(1);
+        event
     }
+}
 
-    fn spawn_enemy(&mut self, x: i32, y: i32) {
-        for enemy in self.enemies.iter_mut() {
-            if !enemy.alive {
-                enemy.revive(x, y);
-                break;
-            }
+pub const ACTIONS_COUNT: usize = 4;
+
+// What the player can do, whatever key or button they pressed for it
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u32)]
+enum Action {
+    MoveLeft = 0,
+    MoveRight = 1,
+    Fire = 2,
+    Pause = 3,
+}
+
+impl Action {
+    const ALL: [Action; ACTIONS_COUNT] = [Action::MoveLeft, Action::MoveRight, Action::Fire, Action::Pause];
+
+    const fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(Action::MoveLeft),
+            1 => Some(Action::MoveRight),
+            2 => Some(Action::Fire),
+            3 => Some(Action::Pause),
+            _ => None,
         }
     }
 
-    fn spawn_bullet(&mut self, x: i32, y: i32) {
-        for bullet in self.bullets.iter_mut() {
+    const fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    const fn message(self) -> Message {
+        match self {
+            Action::MoveLeft => Message::MoveLeft,
+            Action::MoveRight => Message::MoveRight,
+            Action::Fire => Message::Fire,
+            Action::Pause => Message::Pause,
+        }
+    }
+}
+
+// One key and one gamepad button per action, NO_BINDING for none
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Binding {
+    key: i32,
+    button: i32,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Bindings {
+    bindings: [Binding; ACTIONS_COUNT],
+}
+
+impl Bindings {
+    const fn default() -> Self {
+        Self {
+            bindings: [
+                Binding { key: KEY_LEFT, button: GAMEPAD_BUTTON_DPAD_LEFT },
+                Binding { key: KEY_RIGHT, button: GAMEPAD_BUTTON_DPAD_RIGHT },
+                Binding { key: KEY_Z, button: GAMEPAD_BUTTON_RIGHT_TRIGGER },
+                Binding { key: KEY_SPACE, button: GAMEPAD_BUTTON_START },
+            ],
+        }
+    }
+
+    fn get(&self, action: Action) -> Binding {
+        self.bindings.get(action as usize).copied().unwrap_or(Binding { key: NO_BINDING, button: NO_BINDING })
+    }
+
+    fn action_for_key(&self, key: i32) -> Option<Action> {
+        Action::ALL.iter().copied().find(|action| key != NO_BINDING && self.get(*action).key == key)
+    }
+
+    fn action_for_button(&self, button: i32) -> Option<Action> {
+        Action::ALL.iter().copied().find(|action| button != NO_BINDING && self.get(*action).button == button)
+    }
+
+    // An action that already had the key takes over the old one, so a key
+    // never does two things
+    fn bind_key(&mut self, action: Action, key: i32) {
+        let old = self.get(action).key;
+        if let Some(other) = self.action_for_key(key) {
+            if let Some(binding) = self.bindings.get_mut(other as usize) {
+                binding.key = old;
+            }
+        }
+        if let Some(binding) = self.bindings.get_mut(action as usize) {
+            binding.key = key;
+        }
+    }
+
+    fn bind_button(&mut self, action: Action, button: i32) {
+        let old = self.get(action).button;
+        if let Some(other) = self.action_for_button(button) {
+            if let Some(binding) = self.bindings.get_mut(other as usize) {
+                binding.button = old;
+            }
+        }
+        if let Some(binding) = self.bindings.get_mut(action as usize) {
+            binding.button = button;
+        }
+    }
+}
+
+// Escape belongs to the controls screen and L to the language switch
+const fn bindable_key(key: i32) -> bool {
+    key >= 0 && key != KEY_ESCAPE && key != KEY_L
+}
+
+// Names for the keys likely to be bound, the rest go by their number
+fn write_key_name<const N: usize>(label: &mut Label<N>, key: i32) {
+    let name = match key {
+        NO_BINDING => "-",
+        KEY_BACKSPACE => "Backspace",
+        KEY_TAB => "Tab",
+        KEY_ENTER => "Enter",
+        KEY_SHIFT => "Shift",
+        KEY_CTRL => "Ctrl",
+        KEY_ALT => "Alt",
+        KEY_ESCAPE => "Esc",
+        KEY_SPACE => "Space",
+        KEY_LEFT => "Left",
+        KEY_UP => "Up",
+        KEY_RIGHT => "Right",
+        KEY_DOWN => "Down",
+        _ => "",
+    };
+    if !name.is_empty() {
+        label.format(format_args!("{}", name));
+    } else if (KEY_0..=KEY_9).contains(&key) || (KEY_A..=KEY_Z).contains(&key) {
+        // Digits and letters have their ASCII code
+        label.format(format_args!("{}", key as u8 as char));
+    } else {
+        label.format(format_args!("Key {}", key));
+    }
+}
+
+fn write_button_name<const N: usize>(label: &mut Label<N>, button: i32) {
+    let name = if button >= 0 { GAMEPAD_BUTTON_NAMES.get(button as usize) } else { None };
+    match name {
+        Some(name) => label.format(format_args!("{}", name)),
+        None if button == NO_BINDING => label.format(format_args!("-")),
+        None => label.format(format_args!("Button {}", button)),
+    }
+}
+
+// The screen where the player picks an action and presses its new key or
+// button
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ControlsMenu {
+    selected: usize,
+    // The next key or button press goes to the selected action
+    waiting: bool,
+}
+
+impl ControlsMenu {
+    const fn new() -> Self {
+        Self { selected: 0, waiting: false }
+    }
+
+    fn action(&self) -> Action {
+        Action::ALL.get(self.selected).copied().unwrap_or(Action::MoveLeft)
+    }
+
+    fn select(&mut self, delta: i32) {
+        let count = ACTIONS_COUNT as i32;
+        self.selected = ((self.selected as i32 + delta).rem_euclid(count)) as usize;
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Entity {
+    x: i32,
+    y: i32,
+    alive: bool,
+}
+
+impl Entity {
+    const fn new(x: i32, y: i32) -> Self {
+        Self { x, y, alive: true }
+    }
+
+    const fn dead() -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            alive: false,
+        }
+    }
+
+    fn revive(&mut self, x: i32, y: i32) {
+        self.alive = true;
+        self.x = x;
+        self.y = y;
+    }
+
+    fn render(&self, display: &mut Display, camera: &Camera, size: i32, color: Pixel) {
+        if self.alive {
+            let x = self.x - size / 2 - camera.x;
+            let y = self.y - size / 2 - camera.y;
+            display.fill_rect(x + SHADOW_OFFSET, y + SHADOW_OFFSET, size, size, SHADOW_COLOR);
+            display.fill_rect(x, y, size, size, color);
+        }
+    }
+
+    fn overlaps(&self, self_size: i32, that: &Self, that_size: i32) -> bool {
+        if !self.alive || !that.alive {
+            return false;
+        }
+
+        let left1 = self.x - self_size / 2;
+        let right1 = self.x + self_size / 2;
+        let top1 = self.y - self_size / 2;
+        let bottom1 = self.y + self_size / 2;
+
+        let left2 = that.x - that_size / 2;
+        let right2 = that.x + that_size / 2;
+        let top2 = that.y - that_size / 2;
+        let bottom2 = that.y + that_size / 2;
+
+        right1 >= left2 && right2 >= left1 && bottom2 >= top1 && bottom1 >= top2
+    }
+}
+
+// Points floating up from where they were scored
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Popup {
+    x: i32,
+    y: i32,
+    points: usize,
+    age: Seconds,
+    alive: bool,
+}
+
+impl Popup {
+    const fn dead() -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            points: 0,
+            age: 0.0,
+            alive: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Popups {
+    popups: [Popup; POPUPS_CAPACITY],
+}
+
+impl Popups {
+    const fn empty() -> Self {
+        Self { popups: [Popup::dead(); POPUPS_CAPACITY] }
+    }
+
+    // When the pool is full the oldest popup makes room
+    fn spawn(&mut self, x: i32, y: i32, points: usize) {
+        let mut slot = self.popups.iter().position(|popup| !popup.alive);
+        if slot.is_none() {
+            log!(LogLevel::Debug, "popups: all {} slots taken, replacing the oldest", POPUPS_CAPACITY);
+            let mut oldest_age = -1.0;
+            for (i, popup) in self.popups.iter().enumerate() {
+                if popup.age > oldest_age {
+                    oldest_age = popup.age;
+                    slot = Some(i);
+                }
+            }
+        }
+        if let Some(popup) = slot.and_then(|i| self.popups.get_mut(i)) {
+            *popup = Popup { x, y, points, age: 0.0, alive: true };
+        }
+    }
+
+    fn update(&mut self, dt: Seconds) {
+        for popup in self.popups.iter_mut() {
+            popup.age += dt;
+            if popup.age >= POPUP_DURATION {
+                popup.alive = false;
+            }
+        }
+    }
+
+    fn render(&self, display: &mut Display, font: &mut Font, camera: &Camera) {
+        for popup in self.popups.iter().filter(|popup| popup.alive) {
+            let mut text: Label = Label::empty();
+            text.format(format_args!("+{}", popup.points));
+
+            let t = popup.age / POPUP_DURATION;
+            // Quick at first, settling towards the top
+            let rise = (POPUP_RISE as f32 * (1.0 - (1.0 - t) * (1.0 - t))) as i32;
+            // Fades into the background during the second half
+            let step = ((t * 2.0 - 1.0).max(0.0) * POPUP_FADE_STEPS as f32) as u32;
+            let fade = step.min(POPUP_FADE_STEPS) * 255 / POPUP_FADE_STEPS;
+            let style = TextStyle::new(POPUP_COLOR.mix(DISPLAY_BACKGROUND, fade))
+                .shadowed(SHADOW_COLOR.mix(DISPLAY_BACKGROUND, fade), SHADOW_OFFSET / 2);
+
+            let x = popup.x - font.text_width(text.bytes(), POPUP_SCALE) / 2 - camera.x;
+            let y = popup.y - font.text_height(POPUP_SCALE) / 2 - rise - camera.y;
+            font.render_markup(display, text.bytes(), x, y, POPUP_SCALE, style);
+        }
+    }
+}
+
+// Draws HEART_ICON with its top left corner at x, y, one rect per run of
+// set bits. Anything short of full `alpha` is blended over what's there.
+fn render_heart(display: &mut Display, x: i32, y: i32, scale: i32, color: Pixel, alpha: u32) {
+    for (row, bits) in HEART_ICON.iter().enumerate() {
+        let is_set = |col: i32| (bits >> (HEART_ICON_WIDTH - 1 - col)) & 1 == 1;
+        let mut col = 0;
+        while col < HEART_ICON_WIDTH {
+            if !is_set(col) {
+                col += 1;
+                continue;
+            }
+            let start = col;
+            while col < HEART_ICON_WIDTH && is_set(col) {
+                col += 1;
+            }
+            let (run_x, run_y) = (x + start * scale, y + row as i32 * scale);
+            if alpha >= 255 {
+                display.fill_rect(run_x, run_y, (col - start) * scale, scale, color);
+            } else {
+                display.blend_rect(run_x, run_y, (col - start) * scale, scale, color, alpha);
+            }
+        }
+    }
+}
+
+// A see-through square with two bars, where a tap pauses the game
+fn render_pause_button(display: &mut Display) {
+    let button = PAUSE_BUTTON;
+    display.blend_rect(button.x, button.y, button.w, button.h, PAUSE_BUTTON_COLOR, PAUSE_BUTTON_ALPHA);
+    let bars = button.inset(button.w / 4);
+    display.fill_rect(bars.x, bars.y, PAUSE_BUTTON_BAR_WIDTH, bars.h, MESSAGE_COLOR);
+    display.fill_rect(bars.x + bars.w - PAUSE_BUTTON_BAR_WIDTH, bars.y, PAUSE_BUTTON_BAR_WIDTH, bars.h, MESSAGE_COLOR);
+}
+
+// Horizontal run of opaque pixels inside a bitmap
+#[derive(Clone, Copy)]
+struct Span {
+    x: u16,
+    y: u16,
+    len: u16,
+}
+
+#[derive(Clone, Copy)]
+struct GlyphEntry {
+    glyph: usize,
+    scale: i32,
+    paint: Paint,
+    pixels_offset: usize,
+    spans_offset: usize,
+    spans_count: usize,
+}
+
+// Glyphs already scaled and coloured, so drawing one is a handful of row copies.
+// Bitmaps and their spans are packed into arenas that are simply flushed when
+// they run out.
+struct GlyphCache {
+    entries: [GlyphEntry; GLYPH_CACHE_CAPACITY],
+    count: usize,
+    pixels: [Pixel; GLYPH_CACHE_PIXELS],
+    pixels_used: usize,
+    spans: [Span; GLYPH_CACHE_SPANS],
+    spans_used: usize,
+}
+
+impl GlyphCache {
+    fn clear(&mut self) {
+        self.count = 0;
+        self.pixels_used = 0;
+        self.spans_used = 0;
+    }
+
+    fn find(&self, glyph: usize, scale: i32, paint: Paint, size: usize) -> Option<(&[Pixel], &[Span])> {
+        let entries = self.entries.get(0..self.count)?;
+        let entry = entries.iter().find(|e| e.glyph == glyph && e.scale == scale && e.paint == paint)?;
+        let pixels = self.pixels.get(entry.pixels_offset..entry.pixels_offset + size)?;
+        let spans = self.spans.get(entry.spans_offset..entry.spans_offset + entry.spans_count)?;
+        Some((pixels, spans))
+    }
+
+    // Reserves room for a new glyph, returns its offsets into `pixels` and `spans`
+    fn alloc(&mut self, glyph: usize, scale: i32, paint: Paint, size: usize, spans_count: usize) -> Option<(usize, usize)> {
+        if size > GLYPH_CACHE_PIXELS || spans_count > GLYPH_CACHE_SPANS {
+            return None;
+        }
+        if self.count >= GLYPH_CACHE_CAPACITY ||
+           self.pixels_used + size > GLYPH_CACHE_PIXELS ||
+           self.spans_used + spans_count > GLYPH_CACHE_SPANS {
+            log!(LogLevel::Debug, "glyph cache: full, flushing {} glyphs", self.count);
+            self.clear();
+        }
+        let pixels_offset = self.pixels_used;
+        let spans_offset = self.spans_used;
+        let entry = self.entries.get_mut(self.count)?;
+        *entry = GlyphEntry { glyph, scale, paint, pixels_offset, spans_offset, spans_count };
+        self.count += 1;
+        self.pixels_used += size;
+        self.spans_used += spans_count;
+        Some((pixels_offset, spans_offset))
+    }
+}
+
+// Decodes the built-in font sheet: a zero byte followed by N stands for N
+// empty bytes, anything else is 8 pixels, most significant bit first.
+// Skipped pixels are left untouched, so `pixels` must start out zeroed.
+pub fn decompress_font_sheet(bytes: &[u8], pixels: &mut [u8]) {
+    let n = bytes.len();
+    let mut i = 0;
+    let mut pixels_size: usize = 0;
+    while i < n {
+        if let Some(byte) = bytes.get(i).cloned() {
+            if byte == 0x00 {
+                i += 1;
+                if let Some(next_byte) = bytes.get(i).cloned() {
+                    pixels_size += next_byte as usize * 8;
+                } else {
+                    break;
+                }
+                i += 1;
+            } else {
+                for bit_index in 0..BITS_IN_BYTE {
+                    if pixels_size < pixels.len() {
+                        if let Some(pixel_ref) = pixels.get_mut(pixels_size) {
+                            *pixel_ref = ((byte >> (BITS_IN_BYTE - bit_index - 1)) & 1) * 0xFF;
+                        }
+                        pixels_size += 1;
+                    } else {
+                        break;
+                    }
+                }
+                i += 1;
+            }
+        } else {
+            break;
+        }
+    }
+}
+
+// Decodes one UTF-8 sequence, returns the code point and its length in bytes
+fn decode_utf8(bytes: &[u8]) -> Option<(u32, usize)> {
+    let first = *bytes.first()? as u32;
+    let (len, initial, min_code) = match first {
+        0x00..=0x7F => return Some((first, 1)),
+        0xC0..=0xDF => (2, first & 0x1F, 0x80),
+        0xE0..=0xEF => (3, first & 0x0F, 0x800),
+        0xF0..=0xF7 => (4, first & 0x07, 0x10000),
+        _ => return None,
+    };
+    let mut code = initial;
+    for byte in bytes.get(1..len)?.iter() {
+        if byte & 0xC0 != 0x80 {
+            return None;
+        }
+        code = (code << 6) | (*byte as u32 & 0x3F);
+    }
+    // Overlong encodings, surrogates and anything past U+10FFFF
+    if code < min_code || (0xD800..=0xDFFF).contains(&code) || code > 0x10FFFF {
+        return None;
+    }
+    Some((code, len))
+}
+
+// Like decode_utf8, but a malformed sequence yields one
+// UTF8_REPLACEMENT_CHARACTER per byte, so nothing is ever skipped silently.
+// None only at the end of the text.
+fn next_code_point(bytes: &[u8]) -> Option<(u32, usize)> {
+    if bytes.is_empty() {
+        return None;
+    }
+    Some(decode_utf8(bytes).unwrap_or((UTF8_REPLACEMENT_CHARACTER, 1)))
+}
+
+fn read_u32_le(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset + 4)?;
+    Some(b.iter().rev().fold(0, |acc, byte| (acc << 8) | *byte as u32))
+}
+
+#[derive(Clone, Copy)]
+struct TextStyle {
+    color: Pixel,
+    shadow: bool,
+    shadow_color: Pixel,
+    shadow_offset: i32,
+    outline: bool,
+    outline_color: Pixel,
+    gradient: bool,
+    // Bottom colour of the gradient, `color` being the top one
+    gradient_color: Pixel,
+    wave: bool,
+    // In screen pixels
+    wave_amplitude: i32,
+    // Where the wave is at, usually the time since the game started
+    wave_time: Seconds,
+}
+
+impl TextStyle {
+    const fn new(color: Pixel) -> Self {
+        Self {
+            color,
+            shadow: false,
+            shadow_color: SHADOW_COLOR,
+            shadow_offset: SHADOW_OFFSET,
+            outline: false,
+            outline_color: SHADOW_COLOR,
+            gradient: false,
+            gradient_color: color,
+            wave: false,
+            wave_amplitude: TEXT_WAVE_AMPLITUDE,
+            wave_time: 0.0,
+        }
+    }
+
+    const fn shadowed(self, shadow_color: Pixel, shadow_offset: i32) -> Self {
+        Self { shadow: true, shadow_color, shadow_offset, ..self }
+    }
+
+    // One screen pixel thick, whatever the scale
+    const fn outlined(self, outline_color: Pixel) -> Self {
+        Self { outline: true, outline_color, ..self }
+    }
+
+    const fn gradient(self, bottom_color: Pixel) -> Self {
+        Self { gradient: true, gradient_color: bottom_color, ..self }
+    }
+
+    // Glyphs bob up and down in a wave travelling along the text
+    const fn wavy(self, amplitude: i32, time: Seconds) -> Self {
+        Self { wave: true, wave_amplitude: amplitude, wave_time: time, ..self }
+    }
+
+    fn paint(&self) -> Paint {
+        if self.gradient {
+            Paint { top: self.color, bottom: self.gradient_color }
+        } else {
+            Paint::solid(self.color)
+        }
+    }
+
+    // Vertical offset in screen pixels of a glyph `x` font pixels into the text
+    fn wave_offset(&self, x: i32) -> i32 {
+        if !self.wave {
+            return 0;
+        }
+        let phase = self.wave_time * TEXT_WAVE_SPEED + x as f32 * TEXT_WAVE_STEP;
+        let offset = self.wave_amplitude as f32 * unsafe { js_sin(phase) };
+        // Round to nearest
+        (offset + if offset < 0.0 { -0.5 } else { 0.5 }) as i32
+    }
+}
+
+// Parses RRGGBB or RRGGBBAA
+fn parse_hex_color(hex: &[u8]) -> Option<Pixel> {
+    if hex.len() != 6 && hex.len() != 8 {
+        return None;
+    }
+    let mut channels = [0xFF; 4];
+    for (channel, digits) in channels.iter_mut().zip(hex.chunks(2)) {
+        let mut value = 0;
+        for digit in digits {
+            value = value * 16 + (*digit as char).to_digit(16)? as u8;
+        }
+        *channel = value;
+    }
+    Some(Pixel::rgba(channels[0], channels[1], channels[2], channels[3]))
+}
+
+#[derive(Clone, Copy)]
+enum MarkupTag {
+    Color(Pixel),
+    Shadow(bool),
+    Outline(Option<Pixel>),
+    Gradient(Option<Pixel>),
+    Wave(bool),
+    Pop,
+    // `{{` stands for a literal brace
+    Brace,
+}
+
+// Recognises a tag at the start of `bytes`, returns it and its length.
+// Anything that isn't a well formed tag is plain text.
+fn parse_markup_tag(bytes: &[u8]) -> Option<(MarkupTag, usize)> {
+    if bytes.get(0..2)? == b"{{" {
+        return Some((MarkupTag::Brace, 2));
+    }
+    if *bytes.first()? != b'{' {
+        return None;
+    }
+    let end = bytes.iter().position(|b| *b == b'}')?;
+    let tag = match bytes.get(1..end)? {
+        b"/" => MarkupTag::Pop,
+        b"s:on" => MarkupTag::Shadow(true),
+        b"s:off" => MarkupTag::Shadow(false),
+        b"o:off" => MarkupTag::Outline(None),
+        b"g:off" => MarkupTag::Gradient(None),
+        b"w:on" => MarkupTag::Wave(true),
+        b"w:off" => MarkupTag::Wave(false),
+        body => match body.get(0..2)? {
+            b"c:" => MarkupTag::Color(parse_hex_color(body.get(2..)?)?),
+            b"o:" => MarkupTag::Outline(Some(parse_hex_color(body.get(2..)?)?)),
+            b"g:" => MarkupTag::Gradient(Some(parse_hex_color(body.get(2..)?)?)),
+            _ => return None,
+        },
+    };
+    Some((tag, end + 1))
+}
+
+// A glyph of marked up text, positioned relative to the start of the text
+#[derive(Clone, Copy)]
+struct MarkupGlyph {
+    code: u32,
+    // In font pixels
+    x: i32,
+    style: TextStyle,
+}
+
+// Walks marked up text glyph by glyph. Markup:
+//   {c:RRGGBB}         colour, RRGGBBAA with alpha
+//   {s:on} {s:off}     drop shadow
+//   {o:RRGGBB} {o:off} outline in the given colour
+//   {g:RRGGBB} {g:off} gradient from the colour down to the given one
+//   {w:on} {w:off}     wave, timed by the base style
+//   {/}                back to the style before the last tag
+//   {{                 a literal {
+#[derive(Clone, Copy)]
+struct Markup<'a> {
+    text: &'a [u8],
+    styles: [TextStyle; MARKUP_MAX_DEPTH],
+    depth: usize,
+    pen: i32,
+    prev: Option<u32>,
+}
+
+impl<'a> Markup<'a> {
+    fn new(text: &'a [u8], base: TextStyle) -> Self {
+        Self {
+            text,
+            styles: [base; MARKUP_MAX_DEPTH],
+            depth: 0,
+            pen: 0,
+            prev: None,
+        }
+    }
+
+    // The same styles applied to more text
+    fn continued(&self, text: &'a [u8]) -> Self {
+        Self { text, pen: 0, prev: None, ..*self }
+    }
+
+    fn style(&self) -> TextStyle {
+        self.styles.get(self.depth).cloned().unwrap_or(self.styles[0])
+    }
+
+    fn apply(&mut self, tag: MarkupTag) {
+        let mut style = self.style();
+        match tag {
+            MarkupTag::Color(color) => style.color = color,
+            MarkupTag::Shadow(shadow) => style.shadow = shadow,
+            MarkupTag::Outline(outline) => {
+                style.outline = outline.is_some();
+                style.outline_color = outline.unwrap_or(style.outline_color);
+            }
+            MarkupTag::Gradient(gradient) => {
+                style.gradient = gradient.is_some();
+                style.gradient_color = gradient.unwrap_or(style.gradient_color);
+            }
+            MarkupTag::Wave(wave) => style.wave = wave,
+            MarkupTag::Pop => {
+                self.depth = self.depth.saturating_sub(1);
+                return;
+            }
+            MarkupTag::Brace => return,
+        }
+        // Too deeply nested tags replace the innermost style
+        self.depth = (self.depth + 1).min(MARKUP_MAX_DEPTH - 1);
+        if let Some(slot) = self.styles.get_mut(self.depth) {
+            *slot = style;
+        }
+    }
+
+    fn next(&mut self, font: &Font) -> Option<MarkupGlyph> {
+        let code = loop {
+            match parse_markup_tag(self.text) {
+                Some((MarkupTag::Brace, len)) => {
+                    self.text = self.text.get(len..).unwrap_or(&[]);
+                    break b'{' as u32;
+                }
+                Some((tag, len)) => {
+                    self.apply(tag);
+                    self.text = self.text.get(len..).unwrap_or(&[]);
+                }
+                None => {
+                    let (code, len) = next_code_point(self.text)?;
+                    self.text = self.text.get(len..).unwrap_or(&[]);
+                    break code;
+                }
+            }
+        };
+
+        // Tags don't break kerning between the glyphs around them
+        if let Some(prev) = self.prev {
+            self.pen += font.kerning(prev, code);
+        }
+        let glyph = MarkupGlyph {
+            code,
+            x: self.pen - font.glyph_left(code),
+            style: self.style(),
+        };
+        self.pen += font.glyph_advance(code);
+        self.prev = Some(code);
+        Some(glyph)
+    }
+
+    // Width in font pixels of everything walked so far
+    fn width(&self) -> i32 {
+        if self.prev.is_some() {
+            self.pen - FONT_LETTER_SPACING
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum HAlign {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy)]
+enum VAlign {
+    Top,
+    Middle,
+    Bottom,
+}
+
+// Where and how a block of text goes. Lines break at '\n' and wrap at word
+// boundaries to fit the width of `rect`.
+#[derive(Clone, Copy)]
+struct TextLayout {
+    rect: Rect,
+    h_align: HAlign,
+    v_align: VAlign,
+    scale: i32,
+    // Extra space between lines in screen pixels
+    line_spacing: i32,
+}
+
+impl TextLayout {
+    const fn new(rect: Rect, scale: i32) -> Self {
+        Self {
+            rect,
+            h_align: HAlign::Left,
+            v_align: VAlign::Top,
+            scale,
+            line_spacing: 0,
+        }
+    }
+
+    const fn aligned(self, h_align: HAlign, v_align: VAlign) -> Self {
+        Self { h_align, v_align, ..self }
+    }
+
+    const fn spaced(self, line_spacing: i32) -> Self {
+        Self { line_spacing, ..self }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+#[repr(i32)]
+enum FontError {
+    BadMagic = 1,
+    Truncated = 2,
+    TooLarge = 3,
+    UnknownSlot = 4,
+}
+
+struct Font {
+    glyph_width: i32,
+    glyph_height: i32,
+    glyphs_count: usize,
+    // One byte per pixel, glyph after glyph
+    pixels: [u8; FONT_PIXELS_CAPACITY],
+    // Glyph index + 1 for every code point below FONT_MAP_SIZE, 0 if missing
+    glyph_map: [u16; FONT_MAP_SIZE],
+    // Proportional metrics in font pixels, measured from the glyph bitmaps
+    glyph_left: [i8; FONT_GLYPHS_CAPACITY],
+    glyph_advance: [i8; FONT_GLYPHS_CAPACITY],
+    cache: GlyphCache,
+}
+
+impl Font {
+    fn reset(&mut self, glyph_width: i32, glyph_height: i32, glyphs_count: usize) {
+        self.glyph_width = glyph_width;
+        self.glyph_height = glyph_height;
+        self.glyphs_count = glyphs_count;
+        self.glyph_map = [0; FONT_MAP_SIZE];
+        self.cache.clear();
+    }
+
+    // Loads the built-in ASCII and Latin-1 sheets. Glyph indices match the
+    // code points, the control codes in between stay blank.
+    fn load_sheets(&mut self) {
+        let w = FONT_CHAR_WIDTH;
+        let h = FONT_CHAR_HEIGHT;
+        self.reset(w as i32, h as i32, FONT_LATIN1_LAST_CODE as usize + 1);
+        if let Some(pixels) = self.pixels.get_mut(0..self.glyphs_count * w * h) {
+            pixels.fill(0);
+        }
+        self.load_sheet(&COMPRESSED_FONT, FONT_FIRST_CODE, FONT_LAST_CODE);
+        self.load_sheet(&COMPRESSED_FONT_LATIN1, FONT_LATIN1_FIRST_CODE, FONT_LATIN1_LAST_CODE);
+        self.compute_metrics();
+    }
+
+    // Copies an 18x7 sheet of glyphs for `first_code..=last_code` in place
+    fn load_sheet(&mut self, compressed: &[u8], first_code: u32, last_code: u32) {
+        let mut sheet = [0; FONT_IMAGE_WIDTH * FONT_IMAGE_HEIGHT];
+        decompress_font_sheet(compressed, &mut sheet);
+
+        let w = FONT_CHAR_WIDTH;
+        let h = FONT_CHAR_HEIGHT;
+        for code in first_code..=last_code {
+            let char_x = (code - first_code) as usize % FONT_IMAGE_COLS;
+            let char_y = (code - first_code) as usize / FONT_IMAGE_COLS;
+            for y in 0..h {
+                let src_start = (char_y * h + y) * FONT_IMAGE_WIDTH + char_x * w;
+                let dst_start = (code as usize * h + y) * w;
+                if let (Some(dst), Some(src)) = (self.pixels.get_mut(dst_start..dst_start + w),
+                                                 sheet.get(src_start..src_start + w)) {
+                    dst.copy_from_slice(src);
+                }
+            }
+            if let Some(entry) = self.glyph_map.get_mut(code as usize) {
+                *entry = code as u16 + 1;
+            }
+        }
+    }
+
+    // Loads a PC Screen Font version 2, optionally with a unicode table
+    fn load_psf2(&mut self, bytes: &[u8]) -> Result<(), FontError> {
+        let field = |index: usize| read_u32_le(bytes, index * 4).ok_or(FontError::Truncated);
+        if field(0)? != PSF2_MAGIC {
+            return Err(FontError::BadMagic);
+        }
+        let header_size = field(2)? as usize;
+        let flags = field(3)?;
+        let glyphs_count = field(4)? as usize;
+        let glyph_size = field(5)? as usize;
+        let height = field(6)? as usize;
+        let width = field(7)? as usize;
+
+        // Bounded first, so nothing below can overflow a 32-bit usize
+        if width == 0 || width > FONT_GLYPH_MAX_WIDTH || height == 0 || height > FONT_GLYPH_MAX_HEIGHT ||
+           glyphs_count > FONT_GLYPHS_CAPACITY {
+            return Err(FontError::TooLarge);
+        }
+        let pixels_count = glyphs_count.checked_mul(width * height)
+            .filter(|&count| count <= FONT_PIXELS_CAPACITY)
+            .ok_or(FontError::TooLarge)?;
+        let row_size = width.div_ceil(BITS_IN_BYTE);
+        if header_size < PSF2_HEADER_SIZE || glyph_size < row_size * height {
+            return Err(FontError::Truncated);
+        }
+        let glyphs_end = glyphs_count.checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or(FontError::Truncated)?;
+        let glyphs = bytes.get(header_size..glyphs_end).ok_or(FontError::Truncated)?;
+
+        self.reset(width as i32, height as i32, glyphs_count);
+        for (i, pixel_ref) in self.pixels.iter_mut().take(pixels_count).enumerate() {
+            let glyph = i / (width * height);
+            let y = i / width % height;
+            let x = i % width;
+            let byte = glyphs.get(glyph * glyph_size + y * row_size + x / BITS_IN_BYTE).cloned().unwrap_or(0);
+            *pixel_ref = ((byte >> (BITS_IN_BYTE - 1 - x % BITS_IN_BYTE)) & 1) * 0xFF;
+        }
+
+        if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            let table = bytes.get(glyphs_end..).unwrap_or(&[]);
+            let mut glyph = 0;
+            let mut i = 0;
+            let mut in_sequence = false;
+            while glyph < glyphs_count && i < table.len() {
+                match table.get(i).cloned() {
+                    Some(PSF2_SEPARATOR) => {
+                        glyph += 1;
+                        in_sequence = false;
+                        i += 1;
+                    }
+                    // Combining sequences can't be rendered, only single code points
+                    Some(PSF2_START_SEQUENCE) => {
+                        in_sequence = true;
+                        i += 1;
+                    }
+                    _ => match decode_utf8(table.get(i..).unwrap_or(&[])) {
+                        Some((code, len)) => {
+                            if !in_sequence {
+                                if let Some(entry) = self.glyph_map.get_mut(code as usize) {
+                                    if *entry == 0 {
+                                        *entry = glyph as u16 + 1;
+                                    }
+                                }
+                            }
+                            i += len;
+                        }
+                        None => i += 1,
+                    },
+                }
+            }
+        } else {
+            for (code, entry) in self.glyph_map.iter_mut().enumerate().take(glyphs_count) {
+                *entry = code as u16 + 1;
+            }
+        }
+
+        self.compute_metrics();
+        Ok(())
+    }
+
+    // Render '?' for unknown characters
+    fn glyph_index(&self, code: u32) -> usize {
+        let lookup = |code: u32| self.glyph_map.get(code as usize).cloned().unwrap_or(0);
+        let entry = match lookup(code) {
+            0 => lookup(FONT_REPLACEMENT_CODE),
+            entry => entry,
+        };
+        (max(entry as i32, 1) - 1) as usize
+    }
+
+    // Measures every glyph so text can be laid out proportionally. Digits all
+    // get the widest digit's advance, so counting scores don't jitter.
+    fn compute_metrics(&mut self) {
+        let mut digit_width = 0;
+        for glyph in 0..self.glyphs_count {
+            let mut first = self.glyph_width;
+            let mut last = -1;
+            for x in 0..self.glyph_width {
+                for y in 0..self.glyph_height {
+                    if self.glyph_bit(glyph, x, y) {
+                        first = min(first, x);
+                        last = max(last, x);
+                    }
+                }
+            }
+
+            let (left, width) = if last < first {
+                (0, self.glyph_width / 2)
+            } else {
+                (first, last - first + 1)
+            };
+            if let (Some(left_ref), Some(advance_ref)) = (self.glyph_left.get_mut(glyph), self.glyph_advance.get_mut(glyph)) {
+                *left_ref = left as i8;
+                *advance_ref = (width + FONT_LETTER_SPACING) as i8;
+            }
+        }
+
+        for code in b'0' as u32..=b'9' as u32 {
+            let glyph = self.glyph_index(code);
+            digit_width = max(digit_width, self.glyph_advance.get(glyph).cloned().unwrap_or(0) as i32 - FONT_LETTER_SPACING);
+        }
+        for code in b'0' as u32..=b'9' as u32 {
+            let glyph = self.glyph_index(code);
+            if let (Some(left_ref), Some(advance_ref)) = (self.glyph_left.get_mut(glyph), self.glyph_advance.get_mut(glyph)) {
+                let width = *advance_ref as i32 - FONT_LETTER_SPACING;
+                // Centre narrow digits inside the shared width
+                *left_ref -= ((digit_width - width) / 2) as i8;
+                *advance_ref = (digit_width + FONT_LETTER_SPACING) as i8;
+            }
+        }
+    }
+
+    fn glyph_left(&self, code: u32) -> i32 {
+        self.glyph_left.get(self.glyph_index(code)).cloned().unwrap_or(0) as i32
+    }
+
+    fn glyph_advance(&self, code: u32) -> i32 {
+        self.glyph_advance.get(self.glyph_index(code)).cloned().unwrap_or(0) as i32
+    }
+
+    fn kerning(&self, left: u32, right: u32) -> i32 {
+        KERNING_PAIRS.iter()
+            .find(|(l, r, _)| *l as u32 == left && *r as u32 == right)
+            .map_or(0, |(_, _, adjustment)| *adjustment)
+    }
+
+    fn glyph_bit(&self, glyph: usize, x: i32, y: i32) -> bool {
+        if x < 0 || x >= self.glyph_width || y < 0 || y >= self.glyph_height {
+            return false;
+        }
+        let index = (glyph as i32 * self.glyph_height + y) * self.glyph_width + x;
+        self.pixels.get(index as usize) == Some(&0xFF)
+    }
+
+    // Collects the horizontal runs of set pixels in row `y` of a glyph as
+    // (x, length) pairs and returns how many there are
+    fn glyph_runs(&self, glyph: usize, y: i32, runs: &mut [(i32, i32); FONT_GLYPH_MAX_WIDTH]) -> usize {
+        let mut count = 0;
+        let mut run_start = None;
+        for x in 0..=self.glyph_width {
+            let set = self.glyph_bit(glyph, x, y);
+            match (run_start, set) {
+                (None, true) => run_start = Some(x),
+                (Some(run_x), false) => {
+                    if let Some(run) = runs.get_mut(count) {
+                        *run = (run_x, x - run_x);
+                        count += 1;
+                    }
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+        count
+    }
+
+    // Rasterises a scaled and coloured glyph together with its spans
+    fn cache_glyph(&mut self, glyph: usize, scale: i32, paint: Paint) {
+        let w = self.glyph_width * scale;
+        let h = self.glyph_height * scale;
+        let size = (w * h) as usize;
+        let mut runs = [(0, 0); FONT_GLYPH_MAX_WIDTH];
+
+        let mut spans_count = 0;
+        for y in 0..self.glyph_height {
+            spans_count += self.glyph_runs(glyph, y, &mut runs) * scale as usize;
+        }
+        let (pixels_offset, mut span_index) = match self.cache.alloc(glyph, scale, paint, size, spans_count) {
+            Some(offsets) => offsets,
+            None => return,
+        };
+
+        if let Some(bitmap) = self.cache.pixels.get_mut(pixels_offset..pixels_offset + size) {
+            bitmap.fill(Pixel::TRANSPARENT);
+        }
+        for y in 0..self.glyph_height {
+            let count = self.glyph_runs(glyph, y, &mut runs);
+            for &(run_x, run_len) in runs.get(0..count).unwrap_or(&[]) {
+                for dy in 0..scale {
+                    let row = y * scale + dy;
+                    let start = pixels_offset + (row * w + run_x * scale) as usize;
+                    if let Some(pixels) = self.cache.pixels.get_mut(start..start + (run_len * scale) as usize) {
+                        pixels.fill(paint.at(row, h));
+                    }
+                    if let Some(span) = self.cache.spans.get_mut(span_index) {
+                        *span = Span {
+                            x: (run_x * scale) as u16,
+                            y: row as u16,
+                            len: (run_len * scale) as u16,
+                        };
+                        span_index += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    fn render_char(&mut self,
+                   display: &mut Display,
+                   code: u32,
+                   start_x: i32, start_y: i32,
+                   scale: i32,
+                   paint: Paint) {
+        let glyph = self.glyph_index(code);
+        let w = self.glyph_width * scale;
+        let h = self.glyph_height * scale;
+        let size = (w * h) as usize;
+
+        if self.cache.find(glyph, scale, paint, size).is_none() {
+            self.cache_glyph(glyph, scale, paint);
+        }
+
+        match self.cache.find(glyph, scale, paint, size) {
+            Some((bitmap, spans)) => display.blit(start_x, start_y, w, h, bitmap, spans),
+            // Too big for the cache
+            None => {
+                let mut runs = [(0, 0); FONT_GLYPH_MAX_WIDTH];
+                for y in 0..self.glyph_height {
+                    let count = self.glyph_runs(glyph, y, &mut runs);
+                    for &(run_x, run_len) in runs.get(0..count).unwrap_or(&[]) {
+                        for dy in 0..scale {
+                            let row = y * scale + dy;
+                            display.fill_rect(start_x + run_x * scale, start_y + row,
+                                              run_len * scale, 1,
+                                              paint.at(row, h));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Shadows go under outlines and outlines under every glyph's fill, so
+    // neighbouring glyphs never cover each other's fill
+    fn render_markup(&mut self, display: &mut Display, text: &[u8], x: i32, y: i32, scale: i32, base: TextStyle) {
+        self.render_glyphs(display, Markup::new(text, base), x, y, scale);
+    }
+
+    fn render_glyphs(&mut self, display: &mut Display, start: Markup, x: i32, y: i32, scale: i32) {
+        let mut markup = start;
+        while let Some(glyph) = markup.next(self) {
+            let style = glyph.style;
+            if style.shadow {
+                let glyph_x = x + glyph.x * scale + style.shadow_offset;
+                let glyph_y = y + style.wave_offset(glyph.x) + style.shadow_offset;
+                self.render_char(display, glyph.code, glyph_x, glyph_y, scale, Paint::solid(style.shadow_color));
+            }
+        }
+
+        let mut markup = start;
+        while let Some(glyph) = markup.next(self) {
+            let style = glyph.style;
+            if style.outline {
+                let glyph_x = x + glyph.x * scale;
+                let glyph_y = y + style.wave_offset(glyph.x);
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        if dx != 0 || dy != 0 {
+                            self.render_char(display, glyph.code, glyph_x + dx, glyph_y + dy, scale, Paint::solid(style.outline_color));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut markup = start;
+        while let Some(glyph) = markup.next(self) {
+            let style = glyph.style;
+            let glyph_y = y + style.wave_offset(glyph.x);
+            self.render_char(display, glyph.code, x + glyph.x * scale, glyph_y, scale, style.paint());
+        }
+    }
+
+    // Markup tags take up no space
+    fn text_width(&self, text: &[u8], scale: i32) -> i32 {
+        let mut markup = Markup::new(text, TextStyle::new(Pixel::TRANSPARENT));
+        while markup.next(self).is_some() {}
+        markup.width() * scale
+    }
+
+    fn text_height(&self, scale: i32) -> i32 {
+        self.glyph_height * scale
+    }
+
+    // Splits off the first line of `text` that fits in `max_width`, returns
+    // the length of the line and where the next one starts
+    fn next_line(&self, text: &[u8], scale: i32, max_width: i32) -> (usize, usize) {
+        let mut i = 0;
+        let mut width = 0;
+        let mut prev = None;
+        let mut last_space = None;
+        while let Some((code, len)) = next_code_point(text.get(i..).unwrap_or(&[])) {
+            let (code, len) = match parse_markup_tag(text.get(i..).unwrap_or(&[])) {
+                Some((MarkupTag::Brace, len)) => (b'{' as u32, len),
+                Some((_, len)) => {
+                    i += len;
+                    continue;
+                }
+                None => (code, len),
+            };
+            if code == b'\n' as u32 {
+                return (i, i + len);
+            }
+            if let Some(prev) = prev {
+                width += self.kerning(prev, code);
+            }
+            width += self.glyph_advance(code);
+            if code != b' ' as u32 && (width - FONT_LETTER_SPACING) * scale > max_width && i > 0 {
+                return match last_space {
+                    // Spaces at the break belong to neither line
+                    Some(space) => {
+                        let mut next = space;
+                        while text.get(next) == Some(&b' ') {
+                            next += 1;
+                        }
+                        let mut end = space;
+                        while end > 0 && text.get(end - 1) == Some(&b' ') {
+                            end -= 1;
+                        }
+                        (end, next)
+                    }
+                    // A single word wider than the box is cut wherever it overflows
+                    None => (i, i),
+                };
+            }
+            if code == b' ' as u32 {
+                last_space = Some(i);
+            }
+            prev = Some(code);
+            i += len;
+        }
+        (text.len(), text.len())
+    }
+
+    fn text_block_height(&self, text: &[u8], layout: &TextLayout) -> i32 {
+        let mut rest = text;
+        let mut lines = 0;
+        while !rest.is_empty() {
+            let (_, next) = self.next_line(rest, layout.scale, layout.rect.w);
+            rest = rest.get(next..).unwrap_or(&[]);
+            lines += 1;
+        }
+        let line_height = self.text_height(layout.scale) + layout.line_spacing;
+        max(lines * line_height - layout.line_spacing, 0)
+    }
+
+    // Lowers the scale of `layout` until `text` fits in its rect without
+    // cutting a word in two, so longer translations still look right
+    fn fit(&self, text: &[u8], layout: TextLayout) -> TextLayout {
+        let mut layout = layout;
+        while layout.scale > 1 && !self.fits(text, &layout) {
+            layout.scale -= 1;
+        }
+        layout
+    }
+
+    fn fits(&self, text: &[u8], layout: &TextLayout) -> bool {
+        let mut rest = text;
+        while !rest.is_empty() {
+            let (len, next) = self.next_line(rest, layout.scale, layout.rect.w);
+            // Only a word wider than the rect breaks without skipping anything
+            if len == next && next < rest.len() {
+                return false;
+            }
+            rest = rest.get(next..).unwrap_or(&[]);
+        }
+        self.text_block_height(text, layout) <= layout.rect.h
+    }
+
+    // Markup carries over from one line to the next
+    fn render_text(&mut self, display: &mut Display, text: &[u8], layout: &TextLayout, style: TextStyle) {
+        let rect = layout.rect;
+        let block_height = self.text_block_height(text, layout);
+        let mut y = match layout.v_align {
+            VAlign::Top => rect.y,
+            VAlign::Middle => rect.y + (rect.h - block_height) / 2,
+            VAlign::Bottom => rect.y + rect.h - block_height,
+        };
+
+        let mut rest = text;
+        let mut markup = Markup::new(text, style);
+        while !rest.is_empty() {
+            let (len, next) = self.next_line(rest, layout.scale, rect.w);
+            let line = rest.get(0..len).unwrap_or(&[]);
+            let line_width = self.text_width(line, layout.scale);
+            let x = match layout.h_align {
+                HAlign::Left => rect.x,
+                HAlign::Center => rect.x + (rect.w - line_width) / 2,
+                HAlign::Right => rect.x + rect.w - line_width,
+            };
+            markup = markup.continued(line);
+            self.render_glyphs(display, markup, x, y, layout.scale);
+            while markup.next(self).is_some() {}
+            y += self.text_height(layout.scale) + layout.line_spacing;
+            rest = rest.get(next..).unwrap_or(&[]);
+        }
+    }
+}
+
+const FONT_SLOT_BODY: u32 = 0;
+const FONT_SLOT_TITLE: u32 = 1;
+const FONT_SLOT_HUD: u32 = 2;
+
+struct Fonts {
+    // Prompts and everything else
+    body: Font,
+    // Big screen messages
+    title: Font,
+    // Score and health
+    hud: Font,
+}
+
+impl Fonts {
+    fn slot_mut(&mut self, slot: u32) -> Option<&mut Font> {
+        match slot {
+            FONT_SLOT_BODY => Some(&mut self.body),
+            FONT_SLOT_TITLE => Some(&mut self.title),
+            FONT_SLOT_HUD => Some(&mut self.hud),
+            _ => None,
+        }
+    }
+}
+
+const LABEL_CAPACITY: usize = 64;
+// Text of at most N bytes, written with write!
+struct Label<const N: usize = LABEL_CAPACITY> {
+    chars: [u8; N],
+    count: usize,
+}
+
+impl<const N: usize> Label<N> {
+    const fn empty() -> Self {
+        Self {
+            chars: [0; N],
+            count: 0,
+        }
+    }
+
+    fn render(&self,
+              display: &mut Display,
+              font: &mut Font,
+              x: i32, y: i32,
+              scale: i32,
+              color: Pixel) {
+        // Labels may colour parts of themselves with markup
+        let style = TextStyle::new(color).shadowed(SHADOW_COLOR, SHADOW_OFFSET);
+        font.render_markup(display, self.bytes(), x, y, scale, style);
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.chars.get(0..self.count).unwrap_or(&[])
+    }
+
+    fn clear(&mut self) {
+        self.count = 0;
+    }
+
+    // Replaces the text, and warns if it had to be cut short
+    fn format(&mut self, args: fmt::Arguments<'_>) {
+        self.clear();
+        if self.write_fmt(args).is_err() {
+            log!(LogLevel::Warn, "label: cut short at {} bytes: {}", N, core::str::from_utf8(self.bytes()).unwrap_or(""));
+        }
+    }
+}
+
+impl<const N: usize> fmt::Write for Label<N> {
+    // Writes as much as fits, whole characters only, and fails if anything
+    // had to be cut off
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(N - self.count);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        if let (Some(chars), Some(bytes)) = (self.chars.get_mut(self.count..self.count + len), s.as_bytes().get(0..len)) {
+            chars.copy_from_slice(bytes);
+            self.count += len;
+        }
+        if len < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u32)]
+enum Waveform {
+    Square,
+    Saw,
+    // A new random level every period, so noise has a pitch too
+    Noise,
+}
+
+// Attack, decay, sustain and release, with the sustain level held for `hold`
+// seconds since one-shot sounds have no key to let go of
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Envelope {
+    attack: Seconds,
+    decay: Seconds,
+    sustain: f32,
+    hold: Seconds,
+    release: Seconds,
+}
+
+impl Envelope {
+    const fn new(attack: Seconds, decay: Seconds, sustain: f32, hold: Seconds, release: Seconds) -> Self {
+        Self { attack, decay, sustain, hold, release }
+    }
+
+    const fn duration(&self) -> Seconds {
+        self.attack + self.decay + self.hold + self.release
+    }
+
+    fn level(&self, t: Seconds) -> f32 {
+        self.gated_level(t, self.attack + self.decay + self.hold)
+    }
+
+    // Sustains for as long as the note is held, which is `gate` seconds,
+    // then releases from wherever it got to
+    fn gated_level(&self, t: Seconds, gate: Seconds) -> f32 {
+        if t < gate {
+            return self.held_level(t);
+        }
+        let released = t - gate;
+        if released < self.release {
+            self.held_level(gate) * (1.0 - released / self.release)
+        } else {
+            0.0
+        }
+    }
+
+    fn held_level(&self, t: Seconds) -> f32 {
+        if t < self.attack {
+            t / self.attack
+        } else if t < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (t - self.attack) / self.decay
+        } else {
+            self.sustain
+        }
+    }
+}
+
+// An sfxr-style sound: one oscillator whose pitch slides and may jump once
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SoundEffect {
+    waveform: Waveform,
+    // Hz at the start
+    frequency: f32,
+    // Hz per second, negative to fall
+    slide: f32,
+    // Share of a square period spent high
+    duty: f32,
+    // After `jump_time` the pitch is multiplied by `jump_ratio`
+    jump_time: Seconds,
+    jump_ratio: f32,
+    envelope: Envelope,
+    volume: f32,
+}
+
+impl SoundEffect {
+    const fn new(waveform: Waveform, frequency: f32, envelope: Envelope) -> Self {
+        Self {
+            waveform,
+            frequency,
+            slide: 0.0,
+            duty: 0.5,
+            jump_time: 0.0,
+            jump_ratio: 1.0,
+            envelope,
+            volume: 1.0,
+        }
+    }
+
+    const fn sliding(self, slide: f32) -> Self {
+        Self { slide, ..self }
+    }
+
+    const fn duty(self, duty: f32) -> Self {
+        Self { duty, ..self }
+    }
+
+    const fn jumping(self, jump_time: Seconds, jump_ratio: f32) -> Self {
+        Self { jump_time, jump_ratio, ..self }
+    }
+
+    const fn volume(self, volume: f32) -> Self {
+        Self { volume, ..self }
+    }
+
+    fn frequency_at(&self, t: Seconds) -> f32 {
+        let jump = if self.jump_ratio != 1.0 && t >= self.jump_time { self.jump_ratio } else { 1.0 };
+        ((self.frequency + self.slide * t) * jump).max(MIN_FREQUENCY)
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Oscillator {
+    // Where in the current period the oscillator is, from 0 to 1
+    phase: f32,
+    noise: f32,
+}
+
+impl Oscillator {
+    const fn new() -> Self {
+        Self { phase: 0.0, noise: 0.0 }
+    }
+
+    fn next(&mut self, waveform: Waveform, duty: f32, frequency: f32, rng: &mut Rng) -> f32 {
+        let wave = match waveform {
+            Waveform::Square => if self.phase < duty { 1.0 } else { -1.0 },
+            Waveform::Saw => self.phase * 2.0 - 1.0,
+            Waveform::Noise => self.noise,
+        };
+        self.phase += frequency / AUDIO_SAMPLE_RATE as f32;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+            self.noise = (rng.rand() >> 16) as f32 / 32768.0;
+        }
+        wave
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Voice {
+    effect: SoundEffect,
+    active: bool,
+    // Samples since the sound started
+    position: u32,
+    oscillator: Oscillator,
+}
+
+impl Voice {
+    const fn silent() -> Self {
+        Self {
+            effect: SoundEffect::new(Waveform::Square, 0.0, Envelope::new(0.0, 0.0, 0.0, 0.0, 0.0)),
+            active: false,
+            position: 0,
+            oscillator: Oscillator::new(),
+        }
+    }
+
+    fn time(&self) -> Seconds {
+        self.position as f32 / AUDIO_SAMPLE_RATE as f32
+    }
+
+    fn sample(&mut self, rng: &mut Rng) -> f32 {
+        let t = self.time();
+        if t >= self.effect.envelope.duration() {
+            self.active = false;
+            return 0.0;
+        }
+        let wave = self.oscillator.next(self.effect.waveform, self.effect.duty, self.effect.frequency_at(t), rng);
+        self.position += 1;
+        wave * self.effect.envelope.level(t) * self.effect.volume
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Instrument {
+    waveform: Waveform,
+    duty: f32,
+    // The hold is unused, notes sustain until let go
+    envelope: Envelope,
+    volume: f32,
+}
+
+impl Instrument {
+    const fn new(waveform: Waveform, duty: f32, envelope: Envelope, volume: f32) -> Self {
+        Self { waveform, duty, envelope, volume }
+    }
+}
+
+fn note_frequency(note: i32) -> f32 {
+    let semitones = note - NOTE_C4;
+    let octave = semitones.div_euclid(12);
+    let frequency = NOTE_FREQUENCIES.get(semitones.rem_euclid(12) as usize).copied().unwrap_or(0.0);
+    if octave >= 0 {
+        frequency * (1 << min(octave, 16)) as f32
+    } else {
+        frequency / (1 << min(-octave, 16)) as f32
+    }
+}
+
+// Goes from -1 up to 1 and back once per unit of `t`
+fn triangle(t: f32) -> f32 {
+    let t = t - (t as i32) as f32;
+    1.0 - (t * 4.0 - 2.0).abs()
+}
+
+// One column of a song
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Channel {
+    instrument: Instrument,
+    oscillator: Oscillator,
+    // MIDI number, 0 while silent
+    note: u8,
+    // Seconds since the note started, and how long it was held for
+    time: Seconds,
+    gate: Seconds,
+    volume: f32,
+    arpeggio: u8,
+    vibrato: u8,
+}
+
+impl Channel {
+    const fn silent() -> Self {
+        Self {
+            instrument: INSTRUMENTS[0],
+            oscillator: Oscillator::new(),
+            note: 0,
+            time: 0.0,
+            gate: 0.0,
+            volume: 1.0,
+            arpeggio: 0,
+            vibrato: 0,
+        }
+    }
+
+    fn play_cell(&mut self, note: u8, instrument: u8, effect: u8) {
+        if let Some(instrument) = (instrument as usize).checked_sub(1).and_then(|i| INSTRUMENTS.get(i)) {
+            self.instrument = *instrument;
+        }
+        if note >= NOTE_LOWEST {
+            self.note = note;
+            self.time = 0.0;
+            self.gate = Seconds::MAX;
+            self.volume = 1.0;
+            self.arpeggio = 0;
+            self.vibrato = 0;
+        } else if note == NOTE_OFF && self.time < self.gate {
+            self.gate = self.time;
+        }
+        let param = effect & 0xF;
+        match effect >> 4 {
+            EFFECT_ARPEGGIO => self.arpeggio = param,
+            EFFECT_VOLUME => self.volume = param as f32 / 15.0,
+            EFFECT_VIBRATO => self.vibrato = param,
+            _ => {}
+        }
+    }
+
+    fn sample(&mut self, rng: &mut Rng) -> f32 {
+        if self.note == 0 {
+            return 0.0;
+        }
+        let envelope = self.instrument.envelope.gated_level(self.time, self.gate);
+        if self.time >= self.gate && envelope <= 0.0 {
+            self.note = 0;
+            return 0.0;
+        }
+        let mut note = self.note as i32;
+        if self.arpeggio > 0 {
+            let offsets = [0, self.arpeggio as i32, 7];
+            note += offsets.get((self.time * ARPEGGIO_RATE) as usize % offsets.len()).copied().unwrap_or(0);
+        }
+        let mut frequency = note_frequency(note);
+        if self.vibrato > 0 {
+            frequency *= 1.0 + self.vibrato as f32 * VIBRATO_DEPTH * triangle(self.time * VIBRATO_RATE);
+        }
+        let wave = self.oscillator.next(self.instrument.waveform, self.instrument.duty, frequency, rng);
+        self.time += 1.0 / AUDIO_SAMPLE_RATE as f32;
+        wave * envelope * self.instrument.volume * self.volume
+    }
+}
+
+// The six bytes a song starts with, see songs.rs
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SongHeader {
+    tempo: u8,
+    rows: u8,
+    channels: u8,
+    patterns: u8,
+    order_length: u8,
+    loop_start: u8,
+}
+
+impl SongHeader {
+    const fn empty() -> Self {
+        Self { tempo: 0, rows: 0, channels: 0, patterns: 0, order_length: 0, loop_start: 0 }
+    }
+
+    // None unless every order entry and every cell is there
+    fn parse(data: &[u8]) -> Option<Self> {
+        let header = match data.get(0..SONG_HEADER_SIZE)? {
+            &[tempo, rows, channels, patterns, order_length, loop_start] =>
+                Self { tempo, rows, channels, patterns, order_length, loop_start },
+            _ => return None,
+        };
+        let order = data.get(SONG_HEADER_SIZE..SONG_HEADER_SIZE + header.order_length as usize)?;
+        let valid = header.tempo > 0 && header.rows > 0 &&
+            header.channels as usize <= MUSIC_CHANNELS_CAPACITY &&
+            header.loop_start < header.order_length &&
+            order.iter().all(|pattern| *pattern < header.patterns) &&
+            data.len() == header.cell_offset(header.patterns as usize, 0, 0);
+        if valid { Some(header) } else { None }
+    }
+
+    const fn cell_offset(&self, pattern: usize, row: usize, channel: usize) -> usize {
+        let channels = self.channels as usize;
+        SONG_HEADER_SIZE + self.order_length as usize +
+            ((pattern * self.rows as usize + row) * channels + channel) * SONG_CELL_SIZE
+    }
+}
+
+// Plays a song row by row, looping from its loop start forever
+#[repr(C)]
+struct Sequencer {
+    song: Option<Song>,
+    header: SongHeader,
+    // Index in the song's order, and row in that pattern
+    order: usize,
+    row: usize,
+    // Seconds left until the next row
+    row_time: Seconds,
+    // 1 plays the song at its own tempo
+    speed: f32,
+    paused: bool,
+    channels: [Channel; MUSIC_CHANNELS_CAPACITY],
+}
+
+impl Sequencer {
+    const fn new() -> Self {
+        Self {
+            song: None,
+            header: SongHeader::empty(),
+            order: 0,
+            row: 0,
+            row_time: 0.0,
+            speed: 1.0,
+            paused: false,
+            channels: [Channel::silent(); MUSIC_CHANNELS_CAPACITY],
+        }
+    }
+
+    // Starts `song` from the top, unless it's already playing. None stops
+    // the music.
+    fn play(&mut self, song: Option<Song>) {
+        if self.song == song {
+            return;
+        }
+        let header = match song.map(|song| SongHeader::parse(song.data())) {
+            Some(Some(header)) => header,
+            // Silent until the next song, rather than complaining every frame
+            Some(None) => {
+                log!(LogLevel::Error, "music: {:?} is malformed, not playing it", song);
+                SongHeader::empty()
+            }
+            None => SongHeader::empty(),
+        };
+        log!(LogLevel::Info, "music: {:?}", song);
+        *self = Self { song, header, speed: self.speed, paused: self.paused, ..Self::new() };
+    }
+
+    fn play_row(&mut self, data: &[u8]) {
+        let header = self.header;
+        let pattern = data.get(SONG_HEADER_SIZE + self.order).copied().unwrap_or(0) as usize;
+        for (i, channel) in self.channels.iter_mut().take(header.channels as usize).enumerate() {
+            let offset = header.cell_offset(pattern, self.row, i);
+            if let Some(&[note, instrument, effect]) = data.get(offset..offset + SONG_CELL_SIZE) {
+                channel.play_cell(note, instrument, effect);
+            }
+        }
+        self.row_time += 60.0 / (header.tempo as f32 * ROWS_PER_BEAT * self.speed);
+        self.row += 1;
+        if self.row >= header.rows as usize {
+            self.row = 0;
+            self.order += 1;
+            if self.order >= header.order_length as usize {
+                self.order = header.loop_start as usize;
+            }
+        }
+    }
+
+    fn sample(&mut self, rng: &mut Rng) -> f32 {
+        let song = match self.song {
+            Some(song) if !self.paused => song,
+            _ => return 0.0,
+        };
+        if self.row_time <= 0.0 {
+            self.play_row(song.data());
+        }
+        self.row_time -= 1.0 / AUDIO_SAMPLE_RATE as f32;
+        let mut mix = 0.0;
+        for channel in self.channels.iter_mut() {
+            mix += channel.sample(rng);
+        }
+        mix * MUSIC_VOLUME
+    }
+}
+
+// Mixes the music with whatever sound effects are playing
+#[repr(C)]
+struct Synth {
+    music: Sequencer,
+    voices: [Voice; VOICES_CAPACITY],
+    rng: Rng,
+    // Fraction of a sample owed to the next frame
+    carry: f32,
+    // Set while samples are dropped, so that is only logged once
+    overflowing: bool,
+}
+
+impl Synth {
+    const fn new() -> Self {
+        Self {
+            music: Sequencer::new(),
+            voices: [Voice::silent(); VOICES_CAPACITY],
+            rng: Rng::from_seed(987654321),
+            carry: 0.0,
+            overflowing: false,
+        }
+    }
+
+    // Cuts off the sound closest to its end if every voice is busy
+    fn play(&mut self, effect: SoundEffect) {
+        let voice = match self.voices.iter().position(|voice| !voice.active) {
+            Some(free) => free,
+            None => {
+                log!(LogLevel::Debug, "audio: all {} voices busy, cutting the oldest", VOICES_CAPACITY);
+                self.voices.iter()
+                    .enumerate()
+                    .max_by_key(|(_, voice)| voice.position)
+                    .map_or(0, |(i, _)| i)
+            }
+        };
+        if let Some(voice) = self.voices.get_mut(voice) {
+            *voice = Voice { effect, active: true, ..Voice::silent() };
+        }
+    }
+
+    fn sample(&mut self) -> f32 {
+        let mut mix = self.music.sample(&mut self.rng);
+        for voice in self.voices.iter_mut() {
+            if voice.active {
+                mix += voice.sample(&mut self.rng);
+            }
+        }
+        (mix * AUDIO_VOLUME).clamp(-1.0, 1.0)
+    }
+
+    // How many samples `dt` seconds make, never more than the buffer holds
+    fn samples_for(&mut self, dt: Seconds) -> usize {
+        let samples = self.carry + dt * AUDIO_SAMPLE_RATE as f32;
+        let count = samples as usize;
+        self.carry = samples - count as f32;
+        min(count as i32, AUDIO_BUFFER_CAPACITY as i32) as usize
+    }
+
+    // Keeps time whether or not the host reads the samples, so sounds end
+    // when they should either way
+    fn render(&mut self, dt: Seconds, buffer: &mut AudioBuffer) {
+        let mut dropped = 0;
+        for _ in 0..self.samples_for(dt) {
+            let sample = self.sample();
+            if !buffer.push(sample) {
+                dropped += 1;
+            }
+        }
+        if dropped > 0 && !self.overflowing {
+            log!(LogLevel::Debug, "audio: buffer full, dropping samples until the host reads some");
+        }
+        self.overflowing = dropped > 0;
+    }
+}
+
+// Ring buffer of samples for the host. The game writes at
+// `write % AUDIO_BUFFER_CAPACITY` and bumps `write`, the host reads from
+// `read` and bumps it, the same way as InputQueue the other way round.
+#[repr(C)]
+pub struct AudioBuffer {
+    write: u32,
+    read: u32,
+    samples: [f32; AUDIO_BUFFER_CAPACITY],
+}
+
+impl AudioBuffer {
+    fn push(&mut self, sample: f32) -> bool {
+        if self.write.wrapping_sub(self.read) as usize >= AUDIO_BUFFER_CAPACITY {
+            return false;
+        }
+        if let Some(slot) = self.samples.get_mut(self.write as usize % AUDIO_BUFFER_CAPACITY) {
+            *slot = sample;
+        }
+        self.write = self.write.wrapping_add(1);
+        true
+    }
+}
+
+// The finger steering the player. The player follows how far the finger
+// moved since it touched down, so it never jumps under the finger.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct TouchDrag {
+    active: bool,
+    id: i32,
+    start_x: i32,
+    player_start_x: i32,
+    fire_cooldown: Seconds,
+}
+
+impl TouchDrag {
+    const fn none() -> Self {
+        Self { active: false, id: 0, start_x: 0, player_start_x: 0, fire_cooldown: 0.0 }
+    }
+
+    // Fires on the next update, then every TOUCH_FIRE_PERIOD
+    const fn start(id: i32, x: i32, player_x: i32) -> Self {
+        Self { active: true, id, start_x: x, player_start_x: player_x, fire_cooldown: 0.0 }
+    }
+}
+
+// Merges every input source that moves the player. The mouse and a dragging
+// finger ask for a position, a stick for a speed and a held move action for
+// full speed.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct PlayerController {
+    // -1 to 1 with the dead zone taken out
+    stick_x: f32,
+    // Movement too small to show yet, so slow stick moves still add up
+    remainder_x: f32,
+    // One bit per gamepad button past the trigger threshold
+    buttons: u32,
+    // One bit per Action, kept apart so letting go of a key doesn't cancel
+    // the same action held on the gamepad
+    actions_held_by_keys: u32,
+    actions_held_by_buttons: u32,
+    stick_dead_zone: f32,
+    trigger_threshold: f32,
+}
+
+impl PlayerController {
+    const fn new(stick_dead_zone: f32, trigger_threshold: f32) -> Self {
+        Self {
+            stick_x: 0.0,
+            remainder_x: 0.0,
+            buttons: 0,
+            actions_held_by_keys: 0,
+            actions_held_by_buttons: 0,
+            stick_dead_zone,
+            trigger_threshold,
+        }
+    }
+
+    // Rescales what is outside the dead zone back to the full 0 to 1 range
+    fn set_stick(&mut self, value: f32) {
+        let deflection = value.abs().min(1.0);
+        self.stick_x = if deflection <= self.stick_dead_zone {
+            0.0
+        } else {
+            value.signum() * (deflection - self.stick_dead_zone) / (1.0 - self.stick_dead_zone)
+        };
+    }
+
+    // Some(true) if `button` just went past the threshold, Some(false) if it
+    // just went back under, None if nothing changed
+    fn press(&mut self, button: i32, value: f32) -> Option<bool> {
+        if !(0..GAMEPAD_BUTTONS_COUNT).contains(&button) {
+            return None;
+        }
+        let bit = 1u32 << button;
+        let was_pressed = self.buttons & bit != 0;
+        if value >= self.trigger_threshold {
+            self.buttons |= bit;
+        } else {
+            self.buttons &= !bit;
+        }
+        let pressed = self.buttons & bit != 0;
+        if pressed != was_pressed { Some(pressed) } else { None }
+    }
+
+    fn hold(&mut self, action: Action, by_key: bool, held: bool) {
+        let actions = if by_key { &mut self.actions_held_by_keys } else { &mut self.actions_held_by_buttons };
+        if held {
+            *actions |= action.bit();
+        } else {
+            *actions &= !action.bit();
+        }
+    }
+
+    fn held(&self, action: Action) -> bool {
+        (self.actions_held_by_keys | self.actions_held_by_buttons) & action.bit() != 0
+    }
+
+    // Held move actions push as hard as the stick can
+    fn direction(&self) -> f32 {
+        let mut direction = self.stick_x;
+        if self.held(Action::MoveLeft) {
+            direction -= 1.0;
+        }
+        if self.held(Action::MoveRight) {
+            direction += 1.0;
+        }
+        direction.clamp(-1.0, 1.0)
+    }
+
+    // Whole pixels to move the player by this frame
+    fn step(&mut self, dt: Seconds) -> i32 {
+        let direction = self.direction();
+        if direction == 0.0 {
+            self.remainder_x = 0.0;
+            return 0;
+        }
+        let distance = self.remainder_x + direction * PLAYER_MAX_SPEED * dt;
+        let pixels = distance as i32;
+        self.remainder_x = distance - pixels as f32;
+        pixels
+    }
+}
+
+// Everything that starts over with each new game
+#[repr(C)]
+struct Run {
+    player: Entity,
+    player_health: i32,
+    bullets: [Entity; BULLETS_CAPACITY],
+    enemies: [Entity; ENEMIES_CAPACITY],
+    enemy_spawn_cooldown: Seconds,
+    pause: bool,
+    score: usize,
+    // What the score counter shows while it catches up with `score`
+    shown_score: f32,
+    level: usize,
+    // Left on the level screen before play resumes
+    level_time: Seconds,
+    // Time left in the pop of each lost heart
+    heart_pop_time: [Seconds; PLAYER_INITIAL_HEALTH as usize],
+    popups: Popups,
+    rng: Rng,
+    camera: Camera,
+    // Separate from `rng`, so shaking never changes what spawns next
+    shake_rng: Rng,
+    shake_time: Seconds,
+    shake_magnitude: i32,
+    hitstop_time: Seconds,
+    flash_time: Seconds,
+    touch_drag: TouchDrag,
+}
+
+impl Run {
+    const fn new() -> Self {
+        Self {
+            player: Entity::new(DISPLAY_WIDTH as i32 / 2, DISPLAY_HEIGHT as i32 - PLAYER_SIZE),
+            player_health: PLAYER_INITIAL_HEALTH,
+            bullets: [Entity::dead(); BULLETS_CAPACITY],
+            enemies: [Entity::dead(); ENEMIES_CAPACITY],
+            enemy_spawn_cooldown: ENEMY_INITIAL_SPAWN_PERIOD,
+            pause: false,
+            score: 0,
+            shown_score: 0.0,
+            level: 1,
+            level_time: 0.0,
+            heart_pop_time: [0.0; PLAYER_INITIAL_HEALTH as usize],
+            popups: Popups::empty(),
+            rng: Rng::from_seed(123456789),
+            camera: Camera::origin(),
+            shake_rng: Rng::from_seed(192837465),
+            shake_time: 0.0,
+            shake_magnitude: 0,
+            hitstop_time: 0.0,
+            flash_time: 0.0,
+            touch_drag: TouchDrag::none(),
+        }
+    }
+}
+
+// Everything else outlives a game: settings, the screen, and the sound that
+// carries on from one game into the next
+#[repr(C)]
+pub struct State {
+    run: Run,
+    screen: Screen,
+    transition: Transition,
+    score_label: Label,
+    level_label: Label,
+    // Since the game started, paused or not
+    time: Seconds,
+    language: Language,
+    controller: PlayerController,
+    bindings: Bindings,
+    // Set when the player rebinds something, until the host saves it
+    bindings_changed: bool,
+    controls_menu: ControlsMenu,
+    synth: Synth,
+    // Set by the host, plays instead of the song the game would pick
+    music_override: Option<Song>,
+    // Set by the first touch, switches prompts and buttons to touch
+    touch_controls: bool,
+}
+
+impl State {
+    const fn default() -> Self {
+        Self {
+            run: Run::new(),
+            screen: Screen::Title,
+            transition: Transition::none(),
+            score_label: Label::empty(),
+            level_label: Label::empty(),
+            time: 0.0,
+            language: Language::English,
+            controller: PlayerController::new(DEFAULT_STICK_DEAD_ZONE, DEFAULT_TRIGGER_THRESHOLD),
+            bindings: Bindings::default(),
+            bindings_changed: false,
+            controls_menu: ControlsMenu::new(),
+            synth: Synth::new(),
+            music_override: None,
+            touch_controls: false,
+        }
+    }
+
+    fn update(&mut self, dt: Seconds) {
+        self.time += dt;
+        if let Some(screen) = self.transition.update(dt) {
+            self.enter_screen(screen);
+        }
+        self.update_music();
+        if self.run.pause {
+            return;
+        }
+        // Effects keep settling after the game is over
+        self.update_effects(dt);
+        self.update_hud(dt);
+        // Counts down once the transition in is over
+        if self.screen == Screen::Level && !self.transition.active {
+            self.run.level_time -= dt;
+            if self.run.level_time <= 0.0 {
+                self.transition.start(TransitionKind::Wipe, Screen::Playing);
+            }
+        }
+        if self.screen != Screen::Playing {
+            return;
+        }
+        // Hit-stop freezes the simulation for a few frames
+        if self.run.hitstop_time > 0.0 {
+            self.run.hitstop_time -= dt;
+            return;
+        }
+        let step = self.controller.step(dt);
+        if step != 0 {
+            self.move_player_to(self.run.player.x + step);
+        }
+        // Auto-fire for as long as a finger steers the player
+        if self.run.touch_drag.active {
+            self.run.touch_drag.fire_cooldown -= dt;
+            if self.run.touch_drag.fire_cooldown <= 0.0 {
+                self.fire();
+                self.run.touch_drag.fire_cooldown = TOUCH_FIRE_PERIOD;
+            }
+        }
+        // Update bullets
+        for bullet in self.run.bullets.iter_mut() {
+            if bullet.alive {
+                bullet.y -= (BULLET_SPEED as f32 * dt) as i32;
+                if bullet.y + BULLET_SIZE / 2 < 0 {
+                    bullet.alive = false;
+                }
+            }
+        }
+
+        let mut enemy_killed = false;
+        let mut player_hit = false;
+        for enemy in self.run.enemies.iter_mut() {
+            if enemy.alive {
+                {
+                    enemy.y += (ENEMY_SPEED as f32 * dt) as i32;
+                    if enemy.y - ENEMY_SIZE / 2 > DISPLAY_HEIGHT as i32 {
+                        enemy.alive = false;
+                        continue;
+                    }
+                }
+
+                // Check bullet-enemy collision
+                for bullet in self.run.bullets.iter_mut() {
+                    if bullet.alive && enemy.overlaps(ENEMY_SIZE, bullet, BULLET_SIZE) {
+                        bullet.alive = false;
+                        enemy.alive = false;
+                        self.run.score += PLAYER_KILL_REWARD;
+                        self.run.popups.spawn(enemy.x, enemy.y, PLAYER_KILL_REWARD);
+                        enemy_killed = true;
+                        break;
+                    }
+                }
+            }
+            // Check player-enemy collision (only if enemy is still alive)
+            if enemy.alive && enemy.overlaps(ENEMY_SIZE, &self.run.player, PLAYER_SIZE) {
+                enemy.alive = false;
+                self.run.player_health -= 1;
+                if let Some(pop_time) = self.run.heart_pop_time.get_mut(self.run.player_health.max(0) as usize) {
+                    *pop_time = HEART_POP_DURATION;
+                }
+                player_hit = true;
+                if self.run.player_health <= 0 && self.run.player.alive {
+                    self.run.player.alive = false;
+                    self.transition.start(TransitionKind::Dissolve, Screen::GameOver);
+                }
+            }
+        }
+
+        if enemy_killed {
+            self.shake(SHAKE_ENEMY_KILL_MAGNITUDE);
+            self.synth.play(ENEMY_DEATH_SOUND);
+        }
+        if player_hit {
+            self.synth.play(if self.run.player.alive { PLAYER_HIT_SOUND } else { GAME_OVER_SOUND });
+            self.shake(SHAKE_PLAYER_HIT_MAGNITUDE);
+            self.run.hitstop_time = HITSTOP_DURATION;
+            self.run.flash_time = FLASH_DURATION;
+        }
+        if self.run.player.alive && !self.transition.active && self.run.score >= self.run.level * LEVEL_SCORE_STEP {
+            self.run.level += 1;
+            self.transition.start(TransitionKind::Fade, Screen::Level);
+        }
+
+        self.run.enemy_spawn_cooldown -= dt;
+        if self.run.enemy_spawn_cooldown <= 0.0 {
+            let enemy_x = self.run.rng.rand().abs() % (DISPLAY_WIDTH as i32 - ENEMY_SIZE) + ENEMY_SIZE / 2;
+            self.spawn_enemy(enemy_x, -ENEMY_SIZE / 2);
+            let new_cooldown = ENEMY_INITIAL_SPAWN_PERIOD * (1.0 - self.difficulty() * 0.8);
+            self.run.enemy_spawn_cooldown = new_cooldown.max(ENEMY_MIN_SPAWN_PERIOD);
+        }
+    }
+
+    // From 0 at the start to 1 once enemies spawn as fast as they ever will
+    fn difficulty(&self) -> f32 {
+        (self.run.score as f32 * ENEMY_SPAWN_PERIOD_SCORE_FACTOR).min(1.0)
+    }
+
+    fn update_music(&mut self) {
+        let difficulty = self.difficulty();
+        let song = match self.screen {
+            _ if self.music_override.is_some() => self.music_override,
+            Screen::Title | Screen::Controls => Some(Song::Title),
+            // There is no boss, the song is for the hardest stretch of the game
+            Screen::Playing | Screen::Level if difficulty >= 1.0 => Some(Song::Boss),
+            Screen::Playing | Screen::Level => Some(Song::Gameplay),
+            Screen::GameOver => None,
+        };
+        let music = &mut self.synth.music;
+        music.play(song);
+        music.speed = if song == Some(Song::Gameplay) { 1.0 + MUSIC_DIFFICULTY_SPEEDUP * difficulty } else { 1.0 };
+        music.paused = self.run.pause;
+    }
+
+    fn update_hud(&mut self, dt: Seconds) {
+        // Counts up fast while far behind and eases in at the end
+        let target = self.run.score as f32;
+        if self.run.shown_score < target {
+            let speed = ((target - self.run.shown_score) * SCORE_COUNT_RATE).max(SCORE_COUNT_MIN_SPEED);
+            self.run.shown_score = (self.run.shown_score + speed * dt).min(target);
+        }
+        for pop_time in self.run.heart_pop_time.iter_mut() {
+            *pop_time = (*pop_time - dt).max(0.0);
+        }
+        self.run.popups.update(dt);
+        self.update_labels();
+    }
+
+    fn update_labels(&mut self) {
+        self.score_label.format(format_args!("{}: {}", self.language.text(Message::Score), self.run.shown_score as usize));
+        self.level_label.format(format_args!("{} {}", self.language.text(Message::Level), self.run.level));
+    }
+
+    fn set_language(&mut self, language: Language) {
+        self.language = language;
+        // The labels may be on screen while the game isn't running
+        self.update_labels();
+    }
+
+    fn render(&self, display: &mut Display, fonts: &mut Fonts) {
+        // Only what was drawn last frame needs clearing
+        display.begin_frame(DISPLAY_BACKGROUND);
+        // Messages sit right above and below the middle of the screen
+        let half = DISPLAY_HEIGHT as i32 / 2;
+        let upper_half = Rect::new(0, 0, DISPLAY_WIDTH as i32, half);
+        let lower_half = Rect::new(0, half, DISPLAY_WIDTH as i32, half);
+        if self.screen == Screen::Title {
+            let title = TextLayout::new(upper_half, MESSAGE_SCALE).aligned(HAlign::Center, VAlign::Bottom);
+            let title_style = TextStyle::new(TITLE_COLOR)
+                .gradient(TITLE_GRADIENT_COLOR)
+                .outlined(TITLE_OUTLINE_COLOR)
+                .shadowed(SHADOW_COLOR, SHADOW_OFFSET)
+                .wavy(TITLE_WAVE_AMPLITUDE, self.time);
+            fonts.title.render_text(display, TITLE_TEXT, &title, title_style);
+
+            let start_text = self.text(if self.touch_controls { Message::TapToStart } else { Message::PressSpaceToStart });
+            let start = TextLayout::new(lower_half.inset(SCORE_LABEL_PADDING * 2), TEXT_SCALE).aligned(HAlign::Center, VAlign::Top);
+            fonts.body.render_text(display, start_text, &fonts.body.fit(start_text, start), TextStyle::new(MESSAGE_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET));
+
+            // There are no L and C keys to press on a touch screen
+            if !self.touch_controls {
+                let hint_style = TextStyle::new(SCORE_LABEL_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET / 2);
+                let language = TextLayout::new(Rect::screen().inset(COPYRIGHT_PADDING), COPYRIGHT_SCALE).aligned(HAlign::Left, VAlign::Bottom);
+                fonts.body.render_text(display, self.text(Message::ChangeLanguage), &language, hint_style);
+                let mut controls = language;
+                controls.rect.h -= fonts.body.text_height(COPYRIGHT_SCALE) + COPYRIGHT_PADDING;
+                fonts.body.render_text(display, self.text(Message::OpenControls), &controls, hint_style);
+            }
+        } else if self.screen == Screen::Controls {
+            self.render_controls(display, fonts);
+        } else if !self.run.pause {
+            // World space: shaken by the camera
+            self.run.player.render(display, &self.run.camera, PLAYER_SIZE, PLAYER_COLOR);
+            for bullet in self.run.bullets.iter() {
+                bullet.render(display, &self.run.camera, BULLET_SIZE, BULLET_COLOR)
+            }
+            for enemy in self.run.enemies.iter() {
+                enemy.render(display, &self.run.camera, ENEMY_SIZE, ENEMY_COLOR)
+            }
+            self.run.popups.render(display, &mut fonts.hud, &self.run.camera);
+            self.render_vignette(display);
+
+            // Screen space: HUD stays put
+            self.score_label.render(display, &mut fonts.hud,
+                                    SCORE_LABEL_X, SCORE_LABEL_Y,
+                                    HUD_SCALE, SCORE_LABEL_COLOR);
+            // Render health only if player is alive or game just ended
+            if self.run.player.alive || self.screen == Screen::GameOver {
+                let hearts_y = SCORE_LABEL_Y + fonts.hud.text_height(HUD_SCALE) + SCORE_LABEL_PADDING;
+                self.render_hearts(display, HEARTS_X, hearts_y);
+            }
+            if self.touch_controls && self.screen == Screen::Playing {
+                render_pause_button(display);
+            }
+        }
+
+        if !self.run.pause {
+            let copyright = TextLayout::new(Rect::screen().inset(COPYRIGHT_PADDING), COPYRIGHT_SCALE).aligned(HAlign::Right, VAlign::Bottom);
+            fonts.body.render_text(display, self.text(Message::Copyright), &copyright, TextStyle::new(SCORE_LABEL_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET / 2));
+        }
+
+        if self.run.pause {
+            let paused_text = self.text(Message::Paused);
+            let paused = TextLayout::new(Rect::screen().inset(SCORE_LABEL_PADDING), MESSAGE_SCALE).aligned(HAlign::Center, VAlign::Middle);
+            fonts.title.render_text(display, paused_text, &fonts.title.fit(paused_text, paused), TextStyle::new(MESSAGE_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET));
+        }
+
+        if self.screen == Screen::Level {
+            let level_text = self.level_label.bytes();
+            let level = TextLayout::new(upper_half.inset(SCORE_LABEL_PADDING), MESSAGE_SCALE).aligned(HAlign::Center, VAlign::Bottom);
+            fonts.title.render_text(display, level_text, &fonts.title.fit(level_text, level), TextStyle::new(MESSAGE_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET));
+        }
+
+        if self.screen == Screen::GameOver {
+            let game_over_text = self.text(Message::GameOver);
+            let game_over = TextLayout::new(upper_half.inset(SCORE_LABEL_PADDING), MESSAGE_SCALE).aligned(HAlign::Center, VAlign::Bottom);
+            fonts.title.render_text(display, game_over_text, &fonts.title.fit(game_over_text, game_over), TextStyle::new(MESSAGE_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET));
+
+            let score_text = self.score_label.bytes();
+            let score = TextLayout::new(lower_half.inset(SCORE_LABEL_PADDING), TEXT_SCALE).aligned(HAlign::Center, VAlign::Top);
+            fonts.body.render_text(display, score_text, &score, TextStyle::new(SCORE_LABEL_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET));
+
+            // Goes right under the score, however many lines that took
+            let restart_text = self.text(if self.touch_controls { Message::TapToRestart } else { Message::PressSpaceToRestart });
+            let restart_rect = score.rect.below(fonts.body.text_block_height(score_text, &score) + SCORE_LABEL_PADDING);
+            let restart = TextLayout::new(restart_rect, TEXT_SCALE).aligned(HAlign::Center, VAlign::Top).spaced(SCORE_LABEL_PADDING);
+            fonts.body.render_text(display, restart_text, &fonts.body.fit(restart_text, restart), TextStyle::new(MESSAGE_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET));
+        }
+
+        // Transitions cover everything, HUD included
+        self.transition.render(display);
+    }
+
+    // A row per action with its key and button, the selected one highlighted
+    fn render_controls(&self, display: &mut Display, fonts: &mut Fonts) {
+        let heading_text = self.text(Message::Controls);
+        let heading = TextLayout::new(Rect::new(0, 0, DISPLAY_WIDTH as i32, CONTROLS_TOP).inset(SCORE_LABEL_PADDING), MESSAGE_SCALE).aligned(HAlign::Center, VAlign::Middle);
+        fonts.title.render_text(display, heading_text, &fonts.title.fit(heading_text, heading), TextStyle::new(MESSAGE_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET));
+
+        let style = TextStyle::new(SCORE_LABEL_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET / 2);
+        for (i, action) in Action::ALL.iter().enumerate() {
+            let y = CONTROLS_TOP + i as i32 * CONTROLS_ROW_HEIGHT;
+            let selected = i == self.controls_menu.selected;
+            if selected {
+                display.blend_rect(0, y, DISPLAY_WIDTH as i32, CONTROLS_ROW_HEIGHT, CONTROLS_SELECTED_COLOR, CONTROLS_SELECTED_ALPHA);
+            }
+            let row = |x: i32, w: i32| TextLayout::new(Rect::new(x, y, w, CONTROLS_ROW_HEIGHT), CONTROLS_SCALE).aligned(HAlign::Left, VAlign::Middle);
+            let name_text = self.text(action.message());
+            let name = row(SCORE_LABEL_PADDING * 2, CONTROLS_KEY_X - SCORE_LABEL_PADDING * 3);
+            fonts.body.render_text(display, name_text, &fonts.body.fit(name_text, name), style);
+
+            let binding = self.bindings.get(*action);
+            let mut key = Label::<BINDING_NAME_CAPACITY>::empty();
+            let mut button = Label::<BINDING_NAME_CAPACITY>::empty();
+            write_key_name(&mut key, binding.key);
+            write_button_name(&mut button, binding.button);
+            let value_style = if selected && self.controls_menu.waiting { TextStyle::new(CONTROLS_WAITING_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET / 2) } else { style };
+            fonts.body.render_text(display, key.bytes(), &row(CONTROLS_KEY_X, CONTROLS_BUTTON_X - CONTROLS_KEY_X), value_style);
+            fonts.body.render_text(display, button.bytes(), &row(CONTROLS_BUTTON_X, DISPLAY_WIDTH as i32 - CONTROLS_BUTTON_X), value_style);
+        }
+
+        let help_text = self.text(if self.controls_menu.waiting { Message::PressKeyOrButton } else { Message::ControlsHelp });
+        let help_top = CONTROLS_TOP + ACTIONS_COUNT as i32 * CONTROLS_ROW_HEIGHT + SCORE_LABEL_PADDING;
+        let help = TextLayout::new(Rect::new(0, help_top, DISPLAY_WIDTH as i32, DISPLAY_HEIGHT as i32 - help_top).inset(SCORE_LABEL_PADDING * 2), CONTROLS_SCALE).aligned(HAlign::Center, VAlign::Top);
+        fonts.body.render_text(display, help_text, &fonts.body.fit(help_text, help), TextStyle::new(MESSAGE_COLOR).shadowed(SHADOW_COLOR, SHADOW_OFFSET / 2));
+    }
+
+    fn text(&self, message: Message) -> &'static [u8] {
+        self.language.text(message).as_bytes()
+    }
+
+    fn shake(&mut self, magnitude: i32) {
+        // Never let a weak shake cut a stronger one short
+        if self.run.shake_time <= 0.0 || magnitude >= self.run.shake_magnitude {
+            self.run.shake_time = SHAKE_DURATION;
+            self.run.shake_magnitude = magnitude;
+        }
+    }
+
+    fn update_effects(&mut self, dt: Seconds) {
+        self.run.flash_time = (self.run.flash_time - dt).max(0.0);
+        self.run.shake_time = (self.run.shake_time - dt).max(0.0);
+
+        let magnitude = (self.run.shake_magnitude as f32 * self.run.shake_time / SHAKE_DURATION) as i32;
+        if magnitude > 0 {
+            self.run.camera.x = self.run.shake_rng.rand().abs() % (magnitude * 2 + 1) - magnitude;
+            self.run.camera.y = self.run.shake_rng.rand().abs() % (magnitude * 2 + 1) - magnitude;
+        } else {
+            self.run.camera = Camera::origin();
+        }
+    }
+
+    // A heart per point of health, grey once lost. Lost hearts swell and
+    // fade away over their grey slot.
+    fn render_hearts(&self, display: &mut Display, x: i32, y: i32) {
+        let size = HEART_ICON_WIDTH * HEART_SCALE;
+        for (i, pop_time) in self.run.heart_pop_time.iter().enumerate() {
+            let heart_x = x + i as i32 * (size + HEART_SPACING);
+            let full = (i as i32) < self.run.player_health;
+            let color = if full { HEART_COLOR } else { HEART_EMPTY_COLOR };
+            render_heart(display, heart_x + SHADOW_OFFSET / 2, y + SHADOW_OFFSET / 2, HEART_SCALE, SHADOW_COLOR, 255);
+            render_heart(display, heart_x, y, HEART_SCALE, color, 255);
+
+            if *pop_time > 0.0 {
+                let t = 1.0 - pop_time / HEART_POP_DURATION;
+                let scale = HEART_SCALE + (HEART_POP_GROWTH as f32 * t) as i32;
+                // Keeps the swelling heart centred on its slot
+                let offset = (HEART_ICON_WIDTH * (scale - HEART_SCALE)) / 2;
+                let alpha = (255.0 * (1.0 - t)) as u32;
+                render_heart(display, heart_x - offset, y - offset, scale, HEART_COLOR, alpha);
+            }
+        }
+    }
+
+    fn render_vignette(&self, display: &mut Display) {
+        if self.run.flash_time <= 0.0 {
+            return;
+        }
+        let strength = FLASH_MAX_ALPHA * self.run.flash_time / FLASH_DURATION;
+        let w = DISPLAY_WIDTH as i32;
+        let h = DISPLAY_HEIGHT as i32;
+        // Concentric frames, most opaque at the screen edge
+        for band in 0..VIGNETTE_BANDS {
+            let alpha = (255.0 * strength * (VIGNETTE_BANDS - band) as f32 / VIGNETTE_BANDS as f32) as u32;
+            let inset = band * VIGNETTE_BAND_SIZE;
+            let size = VIGNETTE_BAND_SIZE;
+            display.blend_rect(inset, inset, w - inset * 2, size, FLASH_COLOR, alpha);
+            display.blend_rect(inset, h - inset - size, w - inset * 2, size, FLASH_COLOR, alpha);
+            display.blend_rect(inset, inset + size, size, h - (inset + size) * 2, FLASH_COLOR, alpha);
+            display.blend_rect(w - inset - size, inset + size, size, h - (inset + size) * 2, FLASH_COLOR, alpha);
+        }
+    }
+
+    fn spawn_enemy(&mut self, x: i32, y: i32) {
+        for enemy in self.run.enemies.iter_mut() {
+            if !enemy.alive {
+                enemy.revive(x, y);
+                return;
+            }
+        }
+        log!(LogLevel::Warn, "enemies: all {} slots taken, skipping a spawn", ENEMIES_CAPACITY);
+    }
+
+    fn spawn_bullet(&mut self, x: i32, y: i32) {
+        for bullet in self.run.bullets.iter_mut() {
             if !bullet.alive {
                 bullet.revive(x, y);
-                break;
+                self.synth.play(SHOOT_SOUND);
+                return;
             }
         }
+        // Happens all the time when clicking fast, so not worth a warning
+        log!(LogLevel::Debug, "bullets: all {} slots taken, not firing", BULLETS_CAPACITY);
+    }
+
+    fn handle_input(&mut self, event: &InputEvent) {
+        let kind = match InputKind::from_u32(event.kind) {
+            Some(kind) => kind,
+            None => {
+                log!(LogLevel::Warn, "input: unknown kind {}", event.kind);
+                return;
+            }
+        };
+        log!(LogLevel::Debug, "input: {:?} {} ({}, {}) at {}ms", kind, event.code, event.x, event.y, event.time);
+        match kind {
+            InputKind::MouseMove => self.move_player_to(event.x as i32),
+            InputKind::MouseDown if event.code == MOUSE_BUTTON_LEFT => self.fire(),
+            InputKind::KeyDown => self.key_down(event.code),
+            InputKind::KeyUp => self.key_up(event.code),
+            InputKind::TouchStart => self.touch_start(event.code, event.x as i32, event.y as i32),
+            InputKind::TouchMove => self.touch_move(event.code, event.x as i32),
+            InputKind::TouchEnd => self.touch_end(event.code),
+            InputKind::GamepadAxis if event.code == GAMEPAD_AXIS_LEFT_X => self.controller.set_stick(event.x),
+            InputKind::GamepadButtonDown => self.gamepad_button(event.code, event.x),
+            InputKind::GamepadButtonUp => self.gamepad_button(event.code, 0.0),
+            _ => {}
+        }
+    }
+
+    fn key_down(&mut self, key: i32) {
+        if self.screen == Screen::Controls {
+            self.controls_key(key);
+        } else if self.screen == Screen::Title && key == KEY_C && !self.transition.active {
+            self.enter_screen(Screen::Controls);
+        } else if let Some(action) = self.bindings.action_for_key(key) {
+            self.controller.hold(action, true, true);
+            self.action(action);
+        }
+    }
+
+    fn key_up(&mut self, key: i32) {
+        if let Some(action) = self.bindings.action_for_key(key) {
+            self.controller.hold(action, true, false);
+        }
+    }
+
+    fn gamepad_button(&mut self, button: i32, value: f32) {
+        let pressed = match self.controller.press(button, value) {
+            Some(pressed) => pressed,
+            None => return,
+        };
+        // A fired before there were bindings, and still does unless it's
+        // bound to something else
+        let action = self.bindings.action_for_button(button)
+            .or(if button == GAMEPAD_BUTTON_A { Some(Action::Fire) } else { None });
+        if pressed && self.screen == Screen::Controls {
+            self.controls_button(button);
+        } else if pressed && self.screen == Screen::Title && button == GAMEPAD_BUTTON_BACK && !self.transition.active {
+            self.enter_screen(Screen::Controls);
+        } else if let Some(action) = action {
+            self.controller.hold(action, false, pressed);
+            if pressed {
+                self.action(action);
+            }
+        }
+    }
+
+    // Moves are held rather than triggered, see PlayerController::direction
+    fn action(&mut self, action: Action) {
+        match action {
+            Action::MoveLeft | Action::MoveRight => {}
+            Action::Fire => self.fire(),
+            Action::Pause => self.toggle_pause_or_reset(),
+        }
+    }
+
+    fn controls_key(&mut self, key: i32) {
+        if self.controls_menu.waiting {
+            self.controls_menu.waiting = false;
+            // Escape cancels, and so does L where the host lets it through
+            if bindable_key(key) {
+                self.bindings.bind_key(self.controls_menu.action(), key);
+                self.rebound();
+            }
+            return;
+        }
+        match key {
+            KEY_UP => self.controls_menu.select(-1),
+            KEY_DOWN => self.controls_menu.select(1),
+            KEY_ENTER => self.controls_menu.waiting = true,
+            KEY_ESCAPE | KEY_C => self.enter_screen(Screen::Title),
+            _ => {}
+        }
+    }
+
+    fn controls_button(&mut self, button: i32) {
+        if self.controls_menu.waiting {
+            self.controls_menu.waiting = false;
+            self.bindings.bind_button(self.controls_menu.action(), button);
+            self.rebound();
+            return;
+        }
+        match button {
+            GAMEPAD_BUTTON_DPAD_UP => self.controls_menu.select(-1),
+            GAMEPAD_BUTTON_DPAD_DOWN => self.controls_menu.select(1),
+            GAMEPAD_BUTTON_A => self.controls_menu.waiting = true,
+            GAMEPAD_BUTTON_B | GAMEPAD_BUTTON_BACK => self.enter_screen(Screen::Title),
+            _ => {}
+        }
+    }
+
+    fn rebound(&mut self) {
+        let action = self.controls_menu.action();
+        let binding = self.bindings.get(action);
+        log!(LogLevel::Info, "controls: {:?} is key {} and button {}", action, binding.key, binding.button);
+        self.bindings_changed = true;
+        // Whatever was held is now held under another name
+        self.controller.actions_held_by_keys = 0;
+        self.controller.actions_held_by_buttons = 0;
+    }
+
+    // Touches carry the host's touch id in `code`, positions are in game pixels
+    fn touch_start(&mut self, id: i32, x: i32, y: i32) {
+        self.touch_controls = true;
+        // Outside of play, and while paused, any tap does what Space does
+        let pause_button = self.screen == Screen::Playing && !self.run.pause && PAUSE_BUTTON.contains(x, y);
+        if self.screen != Screen::Playing || self.run.pause || pause_button {
+            self.toggle_pause_or_reset();
+        } else if !self.run.touch_drag.active && self.run.player.alive && !self.transition.active {
+            // Other fingers are ignored until the steering one lifts
+            self.run.touch_drag = TouchDrag::start(id, x, self.run.player.x);
+        }
+    }
+
+    fn touch_move(&mut self, id: i32, x: i32) {
+        if self.run.touch_drag.active && self.run.touch_drag.id == id {
+            let drag = self.run.touch_drag;
+            self.move_player_to(drag.player_start_x + x - drag.start_x);
+        }
+    }
+
+    fn touch_end(&mut self, id: i32) {
+        if self.run.touch_drag.active && self.run.touch_drag.id == id {
+            self.run.touch_drag = TouchDrag::none();
+        }
     }
 
-    fn mouse_move(&mut self, x: i32, _y: i32) {
-        if self.player.alive {
-            self.player.x = clamp(x, PLAYER_SIZE / 2, DISPLAY_WIDTH as i32 - PLAYER_SIZE / 2);
+    fn move_player_to(&mut self, x: i32) {
+        if self.run.player.alive && !self.transition.active {
+            self.run.player.x = clamp(x, PLAYER_SIZE / 2, DISPLAY_WIDTH as i32 - PLAYER_SIZE / 2);
         }
     }
 
-    fn mouse_click(&mut self) {
-        if self.player.alive && !self.pause && !self.game_over {
+    fn fire(&mut self) {
+        if self.run.player.alive && !self.run.pause && self.screen == Screen::Playing && !self.transition.active {
             self.spawn_bullet(
-                self.player.x,
-                self.player.y - PLAYER_SIZE / 2 - BULLET_SIZE / 2,
+                self.run.player.x,
+                self.run.player.y - PLAYER_SIZE / 2 - BULLET_SIZE / 2,
             );
         }
     }
 
     fn toggle_pause_or_reset(&mut self) {
-        if !self.game_over {
-            self.pause = !self.pause;
-        } else {
+        if self.transition.active {
+            return;
+        }
+        match self.screen {
+            Screen::Title => self.transition.start(TransitionKind::Wipe, Screen::Playing),
+            Screen::Playing => {
+                self.run.pause = !self.run.pause;
+                log!(LogLevel::Info, "pause: {}", self.run.pause);
+            }
+            Screen::GameOver => self.transition.start(TransitionKind::Fade, Screen::Playing),
+            // Skips the rest of the wait
+            Screen::Level => self.run.level_time = 0.0,
+            // A tap is the only way back without a key or button
+            Screen::Controls => self.enter_screen(Screen::Title),
+        }
+    }
+
+    fn enter_screen(&mut self, screen: Screen) {
+        log!(LogLevel::Info, "screen: {:?} -> {:?}, score {}", self.screen, screen, self.run.score);
+        // Coming back from a level screen carries on with the same game
+        if screen == Screen::Playing && self.screen != Screen::Level {
             self.reset();
+            self.synth.play(PICKUP_SOUND);
+        }
+        if screen == Screen::Level {
+            // A fresh sky for the new level, hidden by the transition
+            self.run.bullets = [Entity::dead(); BULLETS_CAPACITY];
+            self.run.enemies = [Entity::dead(); ENEMIES_CAPACITY];
+            self.run.level_time = LEVEL_SCREEN_DURATION;
         }
+        if screen == Screen::Controls {
+            self.controls_menu = ControlsMenu::new();
+        }
+        self.screen = screen;
     }
 
     fn reset(&mut self) {
-        *self = Self::default();
+        self.run = Run::new();
+        self.update_labels();
     }
 }
 
-static mut FONT: Font = Font {
-    pixels: [0; FONT_IMAGE_WIDTH * FONT_IMAGE_HEIGHT],
-};
+#[cfg(target_arch = "wasm32")]
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+// Everything a single game owns. Hosts create as many as they like and pass
+// the pointer back to every export, so games never share any state.
+// All zeroes is a valid empty Display and Fonts, which keeps both out of the
+// wasm data section and lets a game live in freshly grown memory.
+#[repr(C)]
+pub struct Game {
+    state: State,
+    fonts: Fonts,
+    font_upload: [u8; FONT_UPLOAD_CAPACITY],
+    input: InputQueue,
+    audio: AudioBuffer,
+    display: Display,
+}
+
+impl Game {
+    fn init(&mut self) {
+        // Bundled fonts are known good, but never leave a slot without glyphs
+        self.fonts.body.load_sheets();
+        if let Err(err) = self.fonts.title.load_psf2(TITLE_FONT_PSF) {
+            log!(LogLevel::Error, "title font: {:?}, using the built-in sheets", err);
+            self.fonts.title.load_sheets();
+        }
+        if let Err(err) = self.fonts.hud.load_psf2(HUD_FONT_PSF) {
+            log!(LogLevel::Error, "hud font: {:?}, using the built-in sheets", err);
+            self.fonts.hud.load_sheets();
+        }
+    }
+
+    fn next_frame(&mut self, dt: Seconds) {
+        // Everything the host queued since the last frame, in order
+        while let Some(event) = self.input.pop() {
+            self.state.handle_input(&event);
+        }
+        self.state.update(dt);
+        self.state.synth.render(dt, &mut self.audio);
+        self.state.render(&mut self.display, &mut self.fonts);
+    }
+}
+
+// Bumped whenever an export or a shared layout changes in a way that an
+// older host would get wrong
+const ABI_VERSION: u32 = 2;
+
+// Pixel formats of the display
+// One u32 per pixel, red in the lowest byte, rows top to bottom
+const DISPLAY_FORMAT_RGBA8888: u32 = 1;
+
+// What a game.wasm supports, for the host to check before relying on it.
+// Bits are never reused, a feature the game doesn't have stays clear.
+// Mouse events in the input queue
+const CAPABILITY_MOUSE: u32 = 1 << 0;
+// Key events in the input queue
+const CAPABILITY_KEYBOARD: u32 = 1 << 1;
+// get_dirty_rects and get_dirty_rects_count
+const CAPABILITY_DIRTY_RECTS: u32 = 1 << 2;
+// get_font_upload_buffer and load_font
+const CAPABILITY_FONT_UPLOAD: u32 = 1 << 3;
+// set_language and get_language
+const CAPABILITY_LANGUAGES: u32 = 1 << 4;
+// host_log is imported
+const CAPABILITY_LOG: u32 = 1 << 5;
+// Touch events in the input queue
+const CAPABILITY_TOUCH: u32 = 1 << 6;
+// Gamepad events in the input queue and set_gamepad_dead_zones
+const CAPABILITY_GAMEPAD: u32 = 1 << 7;
+// get/set_key_binding, get/set_button_binding and take_bindings_changed
+const CAPABILITY_BINDINGS: u32 = 1 << 8;
+// get_audio_buffer
+const CAPABILITY_AUDIO: u32 = 1 << 9;
+// Music in the audio buffer, play_song and get_songs_count
+const CAPABILITY_MUSIC: u32 = 1 << 10;
+const CAPABILITIES: u32 = CAPABILITY_MOUSE | CAPABILITY_KEYBOARD | CAPABILITY_DIRTY_RECTS |
+    CAPABILITY_FONT_UPLOAD | CAPABILITY_LANGUAGES | CAPABILITY_TOUCH | CAPABILITY_GAMEPAD | CAPABILITY_BINDINGS |
+    CAPABILITY_AUDIO | CAPABILITY_MUSIC |
+    if cfg!(no_log) { 0 } else { CAPABILITY_LOG };
+
+// Describes this build to the host in one read. Fields are only ever added
+// at the end, `size` tells how many there are.
+#[repr(C)]
+pub struct AbiDescriptor {
+    abi_version: u32,
+    // In bytes
+    size: u32,
+    capabilities: u32,
+    display_width: u32,
+    display_height: u32,
+    display_format: u32,
+    // Where the pixels start in what get_display returns
+    display_pixels_offset: u32,
+    game_size: u32,
+    font_upload_capacity: u32,
+    languages_count: u32,
+    input_queue_capacity: u32,
+    input_event_size: u32,
+    // Where the events start in what get_input_queue returns
+    input_events_offset: u32,
+    audio_sample_rate: u32,
+    audio_buffer_capacity: u32,
+    // Where the samples start in what get_audio_buffer returns
+    audio_samples_offset: u32,
+}
 
-static mut STATE: State = State::default();
-static mut DISPLAY: Display = Display {
-    pixels: [Pixel(0); DISPLAY_WIDTH * DISPLAY_HEIGHT],
+static ABI_DESCRIPTOR: AbiDescriptor = AbiDescriptor {
+    abi_version: ABI_VERSION,
+    size: core::mem::size_of::<AbiDescriptor>() as u32,
+    capabilities: CAPABILITIES,
+    display_width: DISPLAY_WIDTH as u32,
+    display_height: DISPLAY_HEIGHT as u32,
+    display_format: DISPLAY_FORMAT_RGBA8888,
+    display_pixels_offset: core::mem::offset_of!(Display, pixels) as u32,
+    game_size: core::mem::size_of::<Game>() as u32,
+    font_upload_capacity: FONT_UPLOAD_CAPACITY as u32,
+    languages_count: LANGUAGES_COUNT as u32,
+    input_queue_capacity: INPUT_QUEUE_CAPACITY as u32,
+    input_event_size: core::mem::size_of::<InputEvent>() as u32,
+    input_events_offset: core::mem::offset_of!(InputQueue, events) as u32,
+    audio_sample_rate: AUDIO_SAMPLE_RATE,
+    audio_buffer_capacity: AUDIO_BUFFER_CAPACITY as u32,
+    audio_samples_offset: core::mem::offset_of!(AudioBuffer, samples) as u32,
 };
 
 #[no_mangle]
-pub unsafe extern "C" fn init() {
-    FONT.decompress_from_bytes(&COMPRESSED_FONT);
-    STATE = State::default();
+pub extern "C" fn abi_version() -> u32 {
+    ABI_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn get_capabilities() -> u32 {
+    CAPABILITIES
+}
+
+// u32 fields in the order of AbiDescriptor
+#[no_mangle]
+pub extern "C" fn get_abi_descriptor() -> *const AbiDescriptor {
+    &ABI_DESCRIPTOR
+}
+
+// Creates a game in newly grown wasm memory and returns the handle every
+// other export takes, or null when the memory can't grow. Growing detaches
+// views of the old memory buffer on the JS side, so take them afterwards.
+// Games are never freed.
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub extern "C" fn create_game() -> *mut Game {
+    let pages = core::mem::size_of::<Game>().div_ceil(WASM_PAGE_SIZE);
+    let first_page = core::arch::wasm32::memory_grow(0, pages);
+    if first_page == usize::MAX {
+        log!(LogLevel::Error, "create_game: can't grow memory by {} pages", pages);
+        return core::ptr::null_mut();
+    }
+    let game = (first_page * WASM_PAGE_SIZE) as *mut Game;
+    // Grown memory is zeroed and belongs to no other game
+    unsafe { init_game(game) };
+    game
+}
+
+/// Sets up a game in `get_game_size()` zeroed bytes the host allocated
+/// itself, aligned to WASM_PAGE_SIZE at most
+///
+/// # Safety
+/// `game` must be null or point to `get_game_size()` zeroed bytes that no other game uses.
+#[no_mangle]
+pub unsafe extern "C" fn init_game(game: *mut Game) {
+    if game.is_null() {
+        return;
+    }
+    // State is the only part that isn't valid as all zeroes
+    core::ptr::addr_of_mut!((*game).state).write(State::default());
+    (*game).init();
+}
+
+#[no_mangle]
+pub extern "C" fn get_game_size() -> usize {
+    core::mem::size_of::<Game>()
+}
+
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn get_font_upload_buffer(game: *mut Game) -> *mut u8 {
+    match game.as_mut() {
+        Some(game) => game.font_upload.as_mut_ptr(),
+        None => core::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn get_font_upload_capacity() -> usize {
+    FONT_UPLOAD_CAPACITY
+}
+
+/// Replaces the font in `slot` (0 body, 1 title, 2 hud) with the PSF2 data
+/// written to the upload buffer. Returns 0 on success or a FontError code,
+/// in which case the slot keeps its previous font.
+///
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn load_font(game: *mut Game, slot: u32, len: usize) -> i32 {
+    let game = match game.as_mut() {
+        Some(game) => game,
+        None => return FontError::UnknownSlot as i32,
+    };
+    let bytes = match game.font_upload.get(0..len) {
+        Some(bytes) => bytes,
+        None => return FontError::TooLarge as i32,
+    };
+    let font = match game.fonts.slot_mut(slot) {
+        Some(font) => font,
+        None => return FontError::UnknownSlot as i32,
+    };
+    match font.load_psf2(bytes) {
+        Ok(()) => 0,
+        Err(err) => {
+            log!(LogLevel::Warn, "load_font: slot {}: {:?}", slot, err);
+            err as i32
+        }
+    }
 }
 
 #[no_mangle]
@@ -653,37 +3827,242 @@ pub extern "C" fn get_display_height() -> usize {
     DISPLAY_HEIGHT
 }
 
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn get_display(game: *mut Game) -> *mut Display {
+    match game.as_mut() {
+        Some(game) => &mut game.display as *mut Display,
+        None => core::ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn get_dirty_rects(game: *const Game) -> *const Rect {
+    // Array of x, y, w, h quadruples of i32
+    match game.as_ref() {
+        Some(game) => game.display.dirty.rects.as_ptr(),
+        None => core::ptr::null(),
+    }
+}
+
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn get_dirty_rects_count(game: *const Game) -> usize {
+    game.as_ref().map_or(0, |game| game.display.dirty.count)
+}
+
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn next_frame(game: *mut Game, dt: Seconds) {
+    if let Some(game) = game.as_mut() {
+        game.next_frame(dt);
+    }
+}
+
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn get_input_queue(game: *mut Game) -> *mut InputQueue {
+    match game.as_mut() {
+        Some(game) => &mut game.input as *mut InputQueue,
+        None => core::ptr::null_mut(),
+    }
+}
+
+/// Samples the game made since the host last read, see AudioBuffer
+///
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn get_audio_buffer(game: *mut Game) -> *mut AudioBuffer {
+    match game.as_mut() {
+        Some(game) => &mut game.audio as *mut AudioBuffer,
+        None => core::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn get_audio_sample_rate() -> u32 {
+    AUDIO_SAMPLE_RATE
+}
+
+#[no_mangle]
+pub extern "C" fn get_audio_buffer_capacity() -> usize {
+    AUDIO_BUFFER_CAPACITY
+}
+
+/// Plays song `id` (0 title, 1 gameplay, 2 boss) whatever is on screen, to
+/// try songs out. Unknown ids, -1 included, go back to the game's own pick
+/// and return false.
+///
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn play_song(game: *mut Game, id: i32) -> bool {
+    let song = if id >= 0 { Song::from_id(id as u32) } else { None };
+    match game.as_mut() {
+        Some(game) => {
+            game.state.music_override = song;
+            song.is_some()
+        }
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn get_songs_count() -> usize {
+    SONGS_COUNT
+}
+
+/// Switches all UI text to language `id` (0 English, 1 French, 2 German,
+/// 3 Spanish). Returns false and keeps the current language for unknown ids.
+///
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn set_language(game: *mut Game, id: u32) -> bool {
+    match (game.as_mut(), Language::from_id(id)) {
+        (Some(game), Some(language)) => {
+            log!(LogLevel::Info, "language: {}", id);
+            game.state.set_language(language);
+            true
+        }
+        _ => {
+            log!(LogLevel::Warn, "language: unknown id {}", id);
+            false
+        }
+    }
+}
+
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn get_language(game: *const Game) -> u32 {
+    game.as_ref().map_or(0, |game| game.state.language.id())
+}
+
+#[no_mangle]
+pub extern "C" fn get_languages_count() -> usize {
+    LANGUAGES_COUNT
+}
+
+#[no_mangle]
+pub extern "C" fn get_actions_count() -> usize {
+    ACTIONS_COUNT
+}
+
+/// Key code bound to `action` (0 move left, 1 move right, 2 fire, 3 pause),
+/// -1 for none or an unknown action
+///
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
+#[no_mangle]
+pub unsafe extern "C" fn get_key_binding(game: *const Game, action: u32) -> i32 {
+    match (game.as_ref(), Action::from_id(action)) {
+        (Some(game), Some(action)) => game.state.bindings.get(action).key,
+        _ => NO_BINDING,
+    }
+}
+
+/// Gamepad button bound to `action`, -1 for none or an unknown action
+///
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
 #[no_mangle]
-pub unsafe extern "C" fn get_display() -> *mut Display {
-    // Return a raw pointer instead of a mutable reference
-    // This is safer regarding Rust's aliasing rules for static mut
-    &mut DISPLAY as *mut Display
+pub unsafe extern "C" fn get_button_binding(game: *const Game, action: u32) -> i32 {
+    match (game.as_ref(), Action::from_id(action)) {
+        (Some(game), Some(action)) => game.state.bindings.get(action).button,
+        _ => NO_BINDING,
+    }
 }
 
+/// Binds `key` to `action`, -1 unbinds it. An action that had the key gets
+/// `action`'s old one. Returns false for unknown actions, and for Escape and L
+/// which the controls screen and the language switch keep to themselves.
+///
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
 #[no_mangle]
-pub unsafe extern "C" fn next_frame(dt: Seconds) {
-    // Accessing static mut is unsafe, but allowed within an unsafe fn
-    STATE.update(dt);
-    STATE.render(&mut DISPLAY, &FONT);
+pub unsafe extern "C" fn set_key_binding(game: *mut Game, action: u32, key: i32) -> bool {
+    let valid = key == NO_BINDING || bindable_key(key);
+    match (game.as_mut(), Action::from_id(action)) {
+        (Some(game), Some(action)) if valid => {
+            game.state.bindings.bind_key(action, key);
+            true
+        }
+        _ => {
+            log!(LogLevel::Warn, "controls: can't bind key {} to action {}", key, action);
+            false
+        }
+    }
 }
 
+/// Same as set_key_binding for gamepad buttons 0 to 31
+///
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
 #[no_mangle]
-pub unsafe extern "C" fn mouse_move(x: i32, y: i32) {
-    STATE.mouse_move(x, y);
+pub unsafe extern "C" fn set_button_binding(game: *mut Game, action: u32, button: i32) -> bool {
+    let valid = button == NO_BINDING || (0..GAMEPAD_BUTTONS_COUNT).contains(&button);
+    match (game.as_mut(), Action::from_id(action)) {
+        (Some(game), Some(action)) if valid => {
+            game.state.bindings.bind_button(action, button);
+            true
+        }
+        _ => {
+            log!(LogLevel::Warn, "controls: can't bind button {} to action {}", button, action);
+            false
+        }
+    }
 }
 
+/// True once after the player rebinds anything on the controls screen, for
+/// the host to save the bindings. Bindings set by the host don't count.
+///
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
 #[no_mangle]
-pub unsafe extern "C" fn mouse_click() {
-    STATE.mouse_click();
+pub unsafe extern "C" fn take_bindings_changed(game: *mut Game) -> bool {
+    match game.as_mut() {
+        Some(game) => core::mem::replace(&mut game.state.bindings_changed, false),
+        None => false,
+    }
 }
 
+/// Sets how far the stick must move, and a trigger be pulled, before the game
+/// reacts. Both go from 0 to 1. Returns false and keeps the current values if
+/// either is out of range.
+///
+/// # Safety
+/// `game` must be null or a handle from `create_game` or `init_game`.
 #[no_mangle]
-pub unsafe extern "C" fn toggle_pause_or_reset() {
-    STATE.toggle_pause_or_reset();
+pub unsafe extern "C" fn set_gamepad_dead_zones(game: *mut Game, stick: f32, trigger: f32) -> bool {
+    let valid = (0.0..1.0).contains(&stick) && (0.0..=1.0).contains(&trigger) && trigger > 0.0;
+    match game.as_mut() {
+        Some(game) if valid => {
+            log!(LogLevel::Info, "gamepad: stick dead zone {}, trigger threshold {}", stick, trigger);
+            game.state.controller.stick_dead_zone = stick;
+            game.state.controller.trigger_threshold = trigger;
+            true
+        }
+        _ => {
+            log!(LogLevel::Warn, "gamepad: invalid dead zones {}, {}", stick, trigger);
+            false
+        }
+    }
 }
 
 #[allow(dead_code)]
 extern "C" {
     fn js_sin(x: f32) -> f32;
     fn js_cos(x: f32) -> f32;
+    // UTF-8 panic message, the module traps right after
+    fn host_panic(ptr: *const u8, len: usize);
+    // One UTF-8 line at a LogLevel
+    fn host_log(level: u32, ptr: *const u8, len: usize);
 }
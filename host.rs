@@ -0,0 +1,904 @@
+// Native host for game.rs. Runs the game headless, outside of the browser.
+//
+//     ./host bench [frames]
+//     ./host instances [count]
+//     ./host wav [out.wav]
+//     ./host music <song> [seconds] [out.wav]
+//     ./host font-decode <sheet.pbm>
+//     ./host font-encode <sheet.pbm>
+//     ./host font-check <sheet.pbm>...
+//     ./host font-psf <font.psf>...
+//     ./host font-psf-check <font.psf>...
+//
+extern crate game;
+
+use std::alloc::{self, Layout};
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FRAME_DT: f32 = 1.0 / 60.0;
+const DEFAULT_BENCH_FRAMES: usize = 600;
+// Long enough for the enemies to wear the idle player down
+const GAME_OVER_FRAMES: usize = 60 * 60;
+const DEFAULT_INSTANCES: usize = 4;
+const DEFAULT_WAV_PATH: &str = "game.wav";
+const DEFAULT_MUSIC_SECONDS: f32 = 30.0;
+// Games in wasm memory start on a page boundary, so ours do as well
+const GAME_ALIGN: usize = 64 * 1024;
+
+// Mirror InputKind, InputEvent and InputQueue in game.rs
+const INPUT_MOUSE_MOVE: u32 = 1;
+const INPUT_MOUSE_DOWN: u32 = 2;
+const INPUT_KEY_DOWN: u32 = 4;
+const KEY_SPACE: i32 = 32;
+const MOUSE_BUTTON_LEFT: i32 = 0;
+const INPUT_QUEUE_CAPACITY: u32 = 256;
+
+#[repr(C)]
+struct InputEvent {
+    kind: u32,
+    time: u32,
+    code: i32,
+    x: f32,
+    y: f32,
+}
+
+#[repr(C)]
+struct InputQueueHeader {
+    write: u32,
+    read: u32,
+}
+
+// Mirrors AudioBuffer in game.rs, the samples follow
+#[repr(C)]
+struct AudioBufferHeader {
+    write: u32,
+    read: u32,
+}
+
+// A game in zeroed memory of our own, freed on drop
+struct HostGame {
+    game: *mut game::Game,
+    layout: Layout,
+    // Fake clock for input timestamps, in milliseconds
+    time: u32,
+}
+
+// Nothing in a game refers to anything outside of it
+unsafe impl Send for HostGame {}
+
+impl HostGame {
+    fn new() -> Self {
+        let layout = Layout::from_size_align(game::get_game_size(), GAME_ALIGN).unwrap();
+        let game = unsafe { alloc::alloc_zeroed(layout) } as *mut game::Game;
+        if game.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        unsafe { game::init_game(game) };
+        Self { game, layout, time: 0 }
+    }
+
+    // Queues an event for the next frame, the same way index.js does
+    fn push_input(&mut self, kind: u32, code: i32, x: f32, y: f32) {
+        unsafe {
+            let queue = game::get_input_queue(self.game) as *mut u8;
+            let header = &mut *(queue as *mut InputQueueHeader);
+            if header.write.wrapping_sub(header.read) >= INPUT_QUEUE_CAPACITY {
+                eprintln!("WARNING: input queue is full, dropping an event");
+                return;
+            }
+            let events = queue.add(std::mem::size_of::<InputQueueHeader>()) as *mut InputEvent;
+            let slot = events.add((header.write % INPUT_QUEUE_CAPACITY) as usize);
+            slot.write(InputEvent { kind, time: self.time, code, x, y });
+            header.write = header.write.wrapping_add(1);
+        }
+    }
+
+    fn next_frame(&mut self, dt: f32) {
+        unsafe { game::next_frame(self.game, dt) };
+        self.time = self.time.wrapping_add((dt * 1000.0) as u32);
+    }
+
+    fn press_space(&mut self) {
+        self.push_input(INPUT_KEY_DOWN, KEY_SPACE, 0.0, 0.0);
+    }
+
+    fn move_mouse(&mut self, x: f32) {
+        self.push_input(INPUT_MOUSE_MOVE, 0, x, 0.0);
+    }
+
+    fn click(&mut self) {
+        self.push_input(INPUT_MOUSE_DOWN, MOUSE_BUTTON_LEFT, 0.0, 0.0);
+    }
+
+    // Moves every sample the game made since the last call into `samples`,
+    // the same way index.js does
+    fn drain_audio(&mut self, samples: &mut Vec<f32>) {
+        let capacity = game::get_audio_buffer_capacity() as u32;
+        unsafe {
+            let buffer = game::get_audio_buffer(self.game) as *mut u8;
+            let header = &mut *(buffer as *mut AudioBufferHeader);
+            let ring = buffer.add(std::mem::size_of::<AudioBufferHeader>()) as *const f32;
+            while header.read != header.write {
+                samples.push(ring.add((header.read % capacity) as usize).read());
+                header.read = header.read.wrapping_add(1);
+            }
+        }
+    }
+
+    fn pixels(&self) -> &[u32] {
+        let len = game::get_display_width() * game::get_display_height();
+        // Pixels are the first field of the display
+        unsafe { std::slice::from_raw_parts(game::get_display(self.game) as *const u32, len) }
+    }
+}
+
+impl Drop for HostGame {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.game as *mut u8, self.layout) };
+    }
+}
+
+struct FrameStats {
+    name: &'static str,
+    times: Vec<Duration>,
+}
+
+impl FrameStats {
+    fn new(name: &'static str) -> Self {
+        Self { name, times: Vec::new() }
+    }
+
+    fn frame(&mut self, game: &mut HostGame, dt: f32) {
+        let start = Instant::now();
+        game.next_frame(dt);
+        self.times.push(start.elapsed());
+    }
+
+    fn report(&mut self) {
+        self.times.sort();
+        let n = self.times.len();
+        if n == 0 {
+            return;
+        }
+        let total: Duration = self.times.iter().sum();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        println!("{:<10} frames: {:>5}  avg: {:>7.3}ms  p50: {:>7.3}ms  p99: {:>7.3}ms  max: {:>7.3}ms",
+                 self.name, n,
+                 ms(total) / n as f64,
+                 ms(self.times[n / 2]),
+                 ms(self.times[(n * 99 / 100).min(n - 1)]),
+                 ms(self.times[n - 1]));
+    }
+}
+
+fn bench(frames: usize) {
+    let mut game = HostGame::new();
+
+    // Title screen: almost nothing but text
+    let mut title = FrameStats::new("title");
+    for _ in 0..frames {
+        title.frame(&mut game, FRAME_DT);
+    }
+    title.report();
+
+    // Gameplay: sweep the player across the screen, shooting constantly
+    game.press_space();
+    let mut playing = FrameStats::new("playing");
+    for i in 0..frames {
+        let width = game::get_display_width();
+        game.move_mouse((i * 13 % width) as f32);
+        if i % 10 == 0 {
+            game.click();
+        }
+        playing.frame(&mut game, FRAME_DT);
+    }
+    playing.report();
+
+    game.press_space();
+    let mut paused = FrameStats::new("paused");
+    for _ in 0..frames {
+        paused.frame(&mut game, FRAME_DT);
+    }
+    paused.report();
+
+    // Stop shooting until the enemies get through
+    game.press_space();
+    for _ in 0..GAME_OVER_FRAMES {
+        game.next_frame(FRAME_DT);
+    }
+    let mut game_over = FrameStats::new("game over");
+    for _ in 0..frames {
+        game_over.frame(&mut game, FRAME_DT);
+    }
+    game_over.report();
+}
+
+// Starts a game and plays it until long after the enemies won, sweeping
+// and shooting all along. Calls `after_frame` after every frame.
+fn play_scripted(game: &mut HostGame, mut after_frame: impl FnMut(&mut HostGame)) {
+    let width = game::get_display_width();
+    game.press_space();
+    for i in 0..GAME_OVER_FRAMES {
+        game.move_mouse((i * 7 % width) as f32);
+        if i % 9 == 0 {
+            game.click();
+        }
+        game.next_frame(FRAME_DT);
+        after_frame(game);
+    }
+}
+
+// Plays the same scripted game on `count` threads at once. Games sharing any
+// state would drift apart, so every final frame must match the first one.
+fn instances(count: usize) -> bool {
+    let play = || {
+        let mut game = HostGame::new();
+        play_scripted(&mut game, |_| {});
+        game
+    };
+    let threads: Vec<_> = (0..count).map(|_| thread::spawn(play)).collect();
+    let games: Vec<HostGame> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+
+    let mut ok = true;
+    for (i, game) in games.iter().enumerate().skip(1) {
+        if game.pixels() != games[0].pixels() {
+            eprintln!("FAILED: game {} ended on a different frame than game 0", i);
+            ok = false;
+        }
+    }
+    if ok {
+        println!("OK: {} games played side by side ended on the same frame", count);
+    }
+    ok
+}
+
+// Records everything the scripted game plays, to listen to sound effects
+// without a browser
+fn wav(path: &str) -> Result<(), String> {
+    let mut game = HostGame::new();
+    let mut samples = Vec::new();
+    play_scripted(&mut game, |game| game.drain_audio(&mut samples));
+    write_wav(path, game::get_audio_sample_rate(), &samples)?;
+    println!("Wrote {:.1}s of audio to {}", samples.len() as f32 / game::get_audio_sample_rate() as f32, path);
+    Ok(())
+}
+
+// Records a song on its own, for checking songs by ear or by tool. Fails on
+// a malformed or silent song, which is what `make check` relies on.
+fn music(song: i32, seconds: f32, path: &str) -> Result<(), String> {
+    let mut game = HostGame::new();
+    if !unsafe { game::play_song(game.game, song) } {
+        return Err(format!("no song {}, there are {}", song, game::get_songs_count()));
+    }
+    let errors = ERRORS_LOGGED.load(Ordering::Relaxed);
+    let mut samples = Vec::new();
+    for _ in 0..(seconds / FRAME_DT) as usize {
+        game.next_frame(FRAME_DT);
+        game.drain_audio(&mut samples);
+    }
+    if ERRORS_LOGGED.load(Ordering::Relaxed) != errors {
+        return Err(format!("song {} logged errors", song));
+    }
+    if samples.iter().all(|sample| *sample == 0.0) {
+        return Err(format!("song {} is silent", song));
+    }
+    write_wav(path, game::get_audio_sample_rate(), &samples)?;
+    println!("Wrote {:.1}s of song {} to {}", samples.len() as f32 / game::get_audio_sample_rate() as f32, song, path);
+    Ok(())
+}
+
+// Mono 16-bit PCM, the most widely readable kind of WAV
+fn write_wav(path: &str, sample_rate: u32, samples: &[f32]) -> Result<(), String> {
+    let data_size = (samples.len() * 2) as u32;
+    let mut bytes = Vec::with_capacity(44 + samples.len() * 2);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    // Bytes per second, bytes per frame and bits per sample
+    bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes());
+    }
+    fs::write(path, bytes).map_err(|err| format!("could not write {}: {}", path, err))
+}
+
+// Longest run of empty bytes a single 0x00 marker can stand for
+const MAX_ZERO_RUN: usize = 255;
+const BYTES_PER_LINE: usize = 16;
+
+// A font sheet in fonts/ and the array game.rs embeds it as
+struct EmbeddedSheet {
+    file_name: &'static str,
+    const_name: &'static str,
+    first_code: u32,
+    compressed: &'static [u8],
+}
+
+const EMBEDDED_SHEETS: &[EmbeddedSheet] = &[
+    EmbeddedSheet {
+        file_name: "font.pbm",
+        const_name: "COMPRESSED_FONT",
+        first_code: 0x20,
+        compressed: &game::COMPRESSED_FONT,
+    },
+    EmbeddedSheet {
+        file_name: "latin1.pbm",
+        const_name: "COMPRESSED_FONT_LATIN1",
+        first_code: 0xA0,
+        compressed: &game::COMPRESSED_FONT_LATIN1,
+    },
+];
+
+struct Sheet {
+    width: usize,
+    height: usize,
+    // 0xFF for set pixels, 0x00 otherwise, the same as the game's decoder
+    pixels: Vec<u8>,
+}
+
+impl Sheet {
+    fn decode_embedded(embedded: &EmbeddedSheet) -> Self {
+        let (width, height) = (game::FONT_IMAGE_WIDTH, game::FONT_IMAGE_HEIGHT);
+        let mut pixels = vec![0; width * height];
+        game::decompress_font_sheet(embedded.compressed, &mut pixels);
+        Self { width, height, pixels }
+    }
+
+    // Plain (P1) and raw (P4) PBM, where 1 is a set pixel
+    fn read_pbm(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|err| format!("could not read {}: {}", path, err))?;
+        let mut i = 0;
+        let mut token = || -> Option<String> {
+            loop {
+                match bytes.get(i)? {
+                    b'#' => while bytes.get(i).is_some_and(|b| *b != b'\n') { i += 1 },
+                    b if b.is_ascii_whitespace() => i += 1,
+                    _ => break,
+                }
+            }
+            let start = i;
+            while bytes.get(i).is_some_and(|b| !b.is_ascii_whitespace()) {
+                i += 1;
+            }
+            String::from_utf8(bytes[start..i].to_vec()).ok()
+        };
+        let magic = token().ok_or("missing PBM magic")?;
+        let mut dimension = || token().and_then(|t| t.parse::<usize>().ok()).ok_or("bad PBM dimensions");
+        let width = dimension()?;
+        let height = dimension()?;
+
+        let mut pixels = Vec::with_capacity(width * height);
+        match magic.as_str() {
+            "P1" => {
+                let raster = bytes.get(i..).unwrap_or(&[]);
+                for b in raster.iter().filter(|b| !b.is_ascii_whitespace()) {
+                    match b {
+                        b'0' => pixels.push(0x00),
+                        b'1' => pixels.push(0xFF),
+                        _ => return Err(format!("unexpected byte {:?} in PBM raster", *b as char)),
+                    }
+                }
+            }
+            "P4" => {
+                // Exactly one whitespace byte separates the header from the raster
+                let raster = bytes.get(i + 1..).unwrap_or(&[]);
+                let row_size = width.div_ceil(8);
+                for y in 0..height {
+                    for x in 0..width {
+                        let byte = raster.get(y * row_size + x / 8).ok_or("truncated PBM raster")?;
+                        pixels.push(((byte >> (7 - x % 8)) & 1) * 0xFF);
+                    }
+                }
+            }
+            _ => return Err(format!("{} is not a PBM file", path)),
+        }
+        if pixels.len() < width * height {
+            return Err("truncated PBM raster".to_string());
+        }
+        pixels.truncate(width * height);
+        Ok(Self { width, height, pixels })
+    }
+
+    fn write_pbm(&self, path: &str) -> Result<(), String> {
+        let mut text = format!("P1\n# Font sheet for game.rs, encode with ./host font-encode\n{} {}\n", self.width, self.height);
+        for row in self.pixels.chunks(self.width) {
+            text.extend(row.iter().map(|p| if *p != 0 { '1' } else { '0' }));
+            text.push('\n');
+        }
+        fs::write(path, text).map_err(|err| format!("could not write {}: {}", path, err))
+    }
+
+    // Inverse of game::decompress_font_sheet: packs 8 pixels per byte and
+    // replaces every run of empty bytes with 0x00 and the run length
+    fn compress(&self) -> Vec<u8> {
+        let packed: Vec<u8> = self.pixels.chunks(8)
+            .map(|bits| bits.iter().enumerate().fold(0, |byte, (i, p)| byte | (((*p != 0) as u8) << (7 - i))))
+            .collect();
+        let mut result = Vec::new();
+        let mut i = 0;
+        while i < packed.len() {
+            let run = packed[i..].iter().take(MAX_ZERO_RUN).take_while(|b| **b == 0).count();
+            if run > 0 {
+                result.push(0x00);
+                result.push(run as u8);
+                i += run;
+            } else {
+                result.push(packed[i]);
+                i += 1;
+            }
+        }
+        result
+    }
+
+    // The w x h block at x0, y0, or None past the edge of the sheet
+    fn cell(&self, x0: usize, y0: usize, w: usize, h: usize) -> Option<Sheet> {
+        if x0 + w > self.width || y0 + h > self.height {
+            return None;
+        }
+        let pixels = (y0..y0 + h).flat_map(|y| self.pixels[y * self.width + x0..y * self.width + x0 + w].iter().copied()).collect();
+        Some(Self { width: w, height: h, pixels })
+    }
+
+    // Doubles the size with Scale2x, which rounds off diagonals instead of
+    // turning them into stairs. Pixels past the edge repeat the edge.
+    fn scale2x(&self) -> Sheet {
+        let (w, h) = (self.width as isize, self.height as isize);
+        let at = |x: isize, y: isize| self.pixels[(y.clamp(0, h - 1) * w + x.clamp(0, w - 1)) as usize];
+        let mut pixels = vec![0; self.pixels.len() * 4];
+        for y in 0..h {
+            for x in 0..w {
+                let (p, a, b, c, d) = (at(x, y), at(x, y - 1), at(x + 1, y), at(x - 1, y), at(x, y + 1));
+                let e0 = if c == a && c != d && a != b { a } else { p };
+                let e1 = if a == b && a != c && b != d { b } else { p };
+                let e2 = if d == c && d != b && c != a { c } else { p };
+                let e3 = if b == d && b != a && d != c { d } else { p };
+                let top = (y * 2 * w * 2 + x * 2) as usize;
+                let bottom = top + (w * 2) as usize;
+                pixels[top] = e0;
+                pixels[top + 1] = e1;
+                pixels[bottom] = e2;
+                pixels[bottom + 1] = e3;
+            }
+        }
+        Self { width: self.width * 2, height: self.height * 2, pixels }
+    }
+
+    // Position and character of the first pixel that differs
+    fn first_mismatch(&self, that: &Sheet, first_code: u32) -> Option<(usize, usize, char)> {
+        let index = self.pixels.iter().zip(that.pixels.iter()).position(|(a, b)| a != b)?;
+        let (x, y) = (index % self.width, index / self.width);
+        let cell = (y / game::FONT_CHAR_HEIGHT) * game::FONT_IMAGE_COLS + x / game::FONT_CHAR_WIDTH;
+        let code = char::from_u32(first_code + cell as u32).unwrap_or(char::REPLACEMENT_CHARACTER);
+        Some((x, y, code))
+    }
+}
+
+// Sheets are told apart by their file name, whatever directory they are in
+fn embedded_sheet_or_exit(path: &str) -> &'static EmbeddedSheet {
+    let file_name = Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or("");
+    EMBEDDED_SHEETS.iter().find(|sheet| sheet.file_name == file_name).unwrap_or_else(|| {
+        let names: Vec<&str> = EMBEDDED_SHEETS.iter().map(|sheet| sheet.file_name).collect();
+        eprintln!("ERROR: {} is none of the embedded sheets: {}", path, names.join(", "));
+        exit(1);
+    })
+}
+
+fn load_sheet_or_exit(path: &str) -> Sheet {
+    let sheet = Sheet::read_pbm(path).unwrap_or_else(|err| {
+        eprintln!("ERROR: {}", err);
+        exit(1);
+    });
+    if sheet.width != game::FONT_IMAGE_WIDTH || sheet.height != game::FONT_IMAGE_HEIGHT {
+        eprintln!("ERROR: font sheet must be {}x{}, {} is {}x{}",
+                  game::FONT_IMAGE_WIDTH, game::FONT_IMAGE_HEIGHT, path, sheet.width, sheet.height);
+        exit(1);
+    }
+    sheet
+}
+
+fn font_encode(sheet: &Sheet, embedded: &EmbeddedSheet) {
+    let compressed = sheet.compress();
+    let mut text = format!("pub const {}: [u8; {}] = [\n", embedded.const_name, compressed.len());
+    for line in compressed.chunks(BYTES_PER_LINE) {
+        let bytes: Vec<String> = line.iter().map(|b| format!("0x{:02x}", b)).collect();
+        let _ = writeln!(text, "    {},", bytes.join(", "));
+    }
+    text.push_str("];");
+    println!("{}", text);
+}
+
+// Decoding the embedded array must give back the sheet bit for bit, and so
+// must decoding a fresh encoding of it
+fn font_check(sheet: &Sheet, embedded: &EmbeddedSheet) -> bool {
+    let mut ok = true;
+    let name = embedded.const_name;
+    let decoded = Sheet::decode_embedded(embedded);
+    if let Some((x, y, code)) = decoded.first_mismatch(sheet, embedded.first_code) {
+        eprintln!("FAIL: {} differs from the sheet at {}:{} (glyph {:?}), run font-encode", name, x, y, code);
+        ok = false;
+    }
+
+    let compressed = sheet.compress();
+    let mut pixels = vec![0; sheet.width * sheet.height];
+    game::decompress_font_sheet(&compressed, &mut pixels);
+    let round_trip = Sheet { width: sheet.width, height: sheet.height, pixels };
+    if let Some((x, y, code)) = round_trip.first_mismatch(sheet, embedded.first_code) {
+        eprintln!("FAIL: encoder and decoder disagree at {}:{} (glyph {:?})", x, y, code);
+        ok = false;
+    }
+    if ok && compressed[..] != embedded.compressed[..] {
+        eprintln!("FAIL: {} is not what font-encode produces, run font-encode", name);
+        ok = false;
+    }
+
+    if ok {
+        println!("OK: {} bytes of {} decode to the {}x{} sheet", compressed.len(), name, sheet.width, sheet.height);
+    }
+    ok
+}
+
+// Math the browser provides to game.wasm through index.js
+#[no_mangle]
+pub extern "C" fn js_sin(x: f32) -> f32 {
+    x.sin()
+}
+
+#[no_mangle]
+pub extern "C" fn js_cos(x: f32) -> f32 {
+    x.cos()
+}
+
+// Indexed by the game's LogLevel
+const LOG_LEVELS: [&str; 4] = ["error", "warn", "info", "debug"];
+const DEFAULT_LOG_LEVEL: usize = 2;
+const LOG_LEVEL_ERROR: usize = 0;
+
+// Counted whatever GAME_LOG says, for commands that fail on them
+static ERRORS_LOGGED: AtomicUsize = AtomicUsize::new(0);
+
+// Lines above the level named by GAME_LOG (info by default) are dropped
+fn max_log_level() -> usize {
+    static LEVEL: OnceLock<usize> = OnceLock::new();
+    *LEVEL.get_or_init(|| {
+        env::var("GAME_LOG").ok()
+            .and_then(|name| LOG_LEVELS.iter().position(|level| *level == name))
+            .unwrap_or(DEFAULT_LOG_LEVEL)
+    })
+}
+
+/// The game's host_log import, printing to stderr
+///
+/// # Safety
+/// `ptr` must point to `len` readable bytes, as it does for every line the game logs.
+#[no_mangle]
+pub unsafe extern "C" fn host_log(level: u32, ptr: *const u8, len: usize) {
+    let level = level as usize;
+    if level == LOG_LEVEL_ERROR {
+        ERRORS_LOGGED.fetch_add(1, Ordering::Relaxed);
+    }
+    if level > max_log_level() {
+        return;
+    }
+    let bytes = std::slice::from_raw_parts(ptr, len);
+    eprintln!("[{}] {}", LOG_LEVELS.get(level).unwrap_or(&"?"), String::from_utf8_lossy(bytes));
+}
+
+// Glyphs for first_code to last_code in fixed-size cells, left to right
+// and top to bottom
+struct PsfSource {
+    file_name: &'static str,
+    first_code: u32,
+    last_code: u32,
+    cell_width: usize,
+    cell_height: usize,
+}
+
+// A PSF2 font in fonts/ and the sheets next to it that it is built from
+struct PsfFont {
+    file_name: &'static str,
+    sources: &'static [PsfSource],
+    scale2x: bool,
+    glyphs_count: usize,
+}
+
+const PSF2_MAGIC: u32 = 0x864A_B572;
+const PSF2_HEADER_SIZE: u32 = 32;
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+
+const PSF_FONTS: &[PsfFont] = &[
+    // The built-in sheets at twice the size
+    PsfFont {
+        file_name: "title.psf",
+        sources: &[
+            PsfSource { file_name: "font.pbm", first_code: 0x20, last_code: 0x7E, cell_width: game::FONT_CHAR_WIDTH, cell_height: game::FONT_CHAR_HEIGHT },
+            PsfSource { file_name: "latin1.pbm", first_code: 0xA0, last_code: 0xFF, cell_width: game::FONT_CHAR_WIDTH, cell_height: game::FONT_CHAR_HEIGHT },
+        ],
+        scale2x: true,
+        glyphs_count: 256,
+    },
+    PsfFont {
+        file_name: "hud.psf",
+        sources: &[
+            PsfSource { file_name: "hud.pbm", first_code: 0x20, last_code: 0x7E, cell_width: 4, cell_height: 6 },
+        ],
+        scale2x: false,
+        glyphs_count: 128,
+    },
+];
+
+// Fonts are told apart by their file name, like the sheets
+fn psf_font_or_exit(path: &str) -> &'static PsfFont {
+    let file_name = Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or("");
+    PSF_FONTS.iter().find(|font| font.file_name == file_name).unwrap_or_else(|| {
+        let names: Vec<&str> = PSF_FONTS.iter().map(|font| font.file_name).collect();
+        eprintln!("ERROR: {} is none of the PSF fonts: {}", path, names.join(", "));
+        exit(1);
+    })
+}
+
+fn psf_source_path(path: &str, source: &PsfSource) -> PathBuf {
+    Path::new(path).with_file_name(source.file_name)
+}
+
+// Builds the font at `path` from the sheets in the same directory. Glyph
+// slots no sheet covers stay blank and out of the Unicode table.
+fn build_psf(path: &str, font: &PsfFont) -> Result<Vec<u8>, String> {
+    let mut glyphs: Vec<Option<Sheet>> = (0..font.glyphs_count).map(|_| None).collect();
+    for source in font.sources {
+        let source_path = psf_source_path(path, source);
+        let sheet = Sheet::read_pbm(&source_path.to_string_lossy())?;
+        let cols = sheet.width / source.cell_width;
+        for code in source.first_code..=source.last_code {
+            let index = (code - source.first_code) as usize;
+            let (x, y) = (index % cols * source.cell_width, index / cols * source.cell_height);
+            let cell = sheet.cell(x, y, source.cell_width, source.cell_height)
+                .ok_or_else(|| format!("{} has no cell for U+{:04X}", source_path.display(), code))?;
+            let slot = glyphs.get_mut(code as usize).ok_or_else(|| format!("U+{:04X} is past the {} glyphs of {}", code, font.glyphs_count, font.file_name))?;
+            *slot = Some(if font.scale2x { cell.scale2x() } else { cell });
+        }
+    }
+    let first = glyphs.iter().flatten().next().ok_or("no glyphs")?;
+    let (width, height) = (first.width, first.height);
+    if glyphs.iter().flatten().any(|glyph| glyph.width != width || glyph.height != height) {
+        return Err(format!("the sheets of {} have different cell sizes", font.file_name));
+    }
+
+    let row_size = width.div_ceil(8);
+    let header = [PSF2_MAGIC, 0, PSF2_HEADER_SIZE, PSF2_HAS_UNICODE_TABLE,
+                  font.glyphs_count as u32, (row_size * height) as u32, height as u32, width as u32];
+    let mut bytes: Vec<u8> = header.iter().flat_map(|field| field.to_le_bytes()).collect();
+    for glyph in glyphs.iter() {
+        for y in 0..height {
+            for byte in 0..row_size {
+                let bits = (0..8).filter(|bit| {
+                    let x = byte * 8 + bit;
+                    x < width && glyph.as_ref().is_some_and(|glyph| glyph.pixels[y * width + x] != 0)
+                });
+                bytes.push(bits.fold(0, |byte, bit| byte | (0x80 >> bit)));
+            }
+        }
+    }
+    for (code, glyph) in glyphs.iter().enumerate() {
+        if glyph.is_some() {
+            let mut utf8 = [0; 4];
+            bytes.extend_from_slice(char::from_u32(code as u32).unwrap_or(char::REPLACEMENT_CHARACTER).encode_utf8(&mut utf8).as_bytes());
+        }
+        bytes.push(PSF2_SEPARATOR);
+    }
+    Ok(bytes)
+}
+
+// The PSF fonts next to the sheet at `path` that are built from it
+fn psf_fonts_using(path: &str) -> Vec<(String, &'static PsfFont)> {
+    let file_name = Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or("");
+    PSF_FONTS.iter()
+        .filter(|font| font.sources.iter().any(|source| source.file_name == file_name))
+        .map(|font| (Path::new(path).with_file_name(font.file_name).to_string_lossy().into_owned(), font))
+        .filter(|(font_path, _)| Path::new(font_path).exists())
+        .collect()
+}
+
+fn font_psf(path: &str, font: &PsfFont) -> Result<(), String> {
+    let bytes = build_psf(path, font)?;
+    fs::write(path, &bytes).map_err(|err| format!("could not write {}: {}", path, err))?;
+    println!("Wrote {} bytes to {}", bytes.len(), path);
+    Ok(())
+}
+
+// The committed font must be exactly what font-psf builds from its sheets
+fn font_psf_check(path: &str, font: &PsfFont) -> bool {
+    let built = match build_psf(path, font) {
+        Ok(built) => built,
+        Err(err) => {
+            eprintln!("FAIL: {}", err);
+            return false;
+        }
+    };
+    let committed = match fs::read(path) {
+        Ok(committed) => committed,
+        Err(err) => {
+            eprintln!("FAIL: could not read {}: {}", path, err);
+            return false;
+        }
+    };
+    match built.iter().zip(committed.iter()).position(|(a, b)| a != b) {
+        None if built.len() == committed.len() => {
+            let sheets: Vec<&str> = font.sources.iter().map(|source| source.file_name).collect();
+            println!("OK: {} is built from {}", path, sheets.join(" and "));
+            true
+        }
+        offset => {
+            let offset = offset.unwrap_or(built.len().min(committed.len()));
+            eprintln!("FAIL: {} differs from its sheets at byte {}, run font-psf", path, offset);
+            false
+        }
+    }
+}
+
+fn usage(program: &str) {
+    eprintln!("Usage: {} <command> [args]", program);
+    eprintln!("Commands:");
+    eprintln!("    bench [frames]               measure next_frame times on every screen (default {} frames each)", DEFAULT_BENCH_FRAMES);
+    eprintln!("    instances [count]            play the same game on several threads and compare the results (default {})", DEFAULT_INSTANCES);
+    eprintln!("    wav [out.wav]                record the sound of a scripted game (default {})", DEFAULT_WAV_PATH);
+    eprintln!("    music <song> [seconds] [out.wav]  record a song on its own (default {}s to {})", DEFAULT_MUSIC_SECONDS, DEFAULT_WAV_PATH);
+    eprintln!("    font-decode <sheet.pbm>      write the embedded sheet out as an editable PBM");
+    eprintln!("    font-encode <sheet.pbm>      print the sheet as its COMPRESSED_FONT* declaration");
+    eprintln!("    font-check <sheet.pbm>...    verify the embedded arrays, the encoder and the PSF fonts match the sheets");
+    eprintln!("    font-psf <font.psf>...       build PSF2 fonts from the sheets next to them");
+    eprintln!("    font-psf-check <font.psf>... verify PSF2 fonts are what font-psf builds");
+    eprintln!("Sheets:");
+    for sheet in EMBEDDED_SHEETS {
+        eprintln!("    {:<28} {}", sheet.file_name, sheet.const_name);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args.first().map(String::as_str).unwrap_or("host");
+    match args.get(1).map(String::as_str) {
+        Some("bench") => {
+            let frames = match args.get(2).map(|s| s.parse::<usize>()) {
+                None => DEFAULT_BENCH_FRAMES,
+                Some(Ok(frames)) if frames > 0 => frames,
+                Some(_) => {
+                    eprintln!("ERROR: frames must be a positive integer");
+                    exit(1);
+                }
+            };
+            bench(frames);
+        }
+        Some("instances") => {
+            let count = match args.get(2).map(|s| s.parse::<usize>()) {
+                None => DEFAULT_INSTANCES,
+                Some(Ok(count)) if count > 0 => count,
+                Some(_) => {
+                    eprintln!("ERROR: count must be a positive integer");
+                    exit(1);
+                }
+            };
+            if !instances(count) {
+                exit(1);
+            }
+        }
+        Some("wav") => {
+            let path = args.get(2).map_or(DEFAULT_WAV_PATH, String::as_str);
+            if let Err(err) = wav(path) {
+                eprintln!("ERROR: {}", err);
+                exit(1);
+            }
+        }
+        Some("music") => {
+            let song = match args.get(2).map(|s| s.parse::<i32>()) {
+                Some(Ok(song)) => song,
+                _ => {
+                    eprintln!("ERROR: song must be a number, 0 to {}", game::get_songs_count() - 1);
+                    exit(1);
+                }
+            };
+            let seconds = match args.get(3).map(|s| s.parse::<f32>()) {
+                None => DEFAULT_MUSIC_SECONDS,
+                Some(Ok(seconds)) if seconds > 0.0 => seconds,
+                Some(_) => {
+                    eprintln!("ERROR: seconds must be a positive number");
+                    exit(1);
+                }
+            };
+            let path = args.get(4).map_or(DEFAULT_WAV_PATH, String::as_str);
+            if let Err(err) = music(song, seconds, path) {
+                eprintln!("ERROR: {}", err);
+                exit(1);
+            }
+        }
+        Some("font-decode") => {
+            let path = args.get(2).unwrap_or_else(|| {
+                eprintln!("ERROR: no output file is provided");
+                exit(1);
+            });
+            if let Err(err) = Sheet::decode_embedded(embedded_sheet_or_exit(path)).write_pbm(path) {
+                eprintln!("ERROR: {}", err);
+                exit(1);
+            }
+        }
+        Some("font-encode") => {
+            let path = args.get(2).unwrap_or_else(|| {
+                eprintln!("ERROR: no font sheet is provided");
+                exit(1);
+            });
+            font_encode(&load_sheet_or_exit(path), embedded_sheet_or_exit(path));
+        }
+        Some("font-check") => {
+            let paths = args.get(2..).unwrap_or(&[]);
+            if paths.is_empty() {
+                eprintln!("ERROR: no font sheet is provided");
+                exit(1);
+            }
+            let mut ok = true;
+            let mut fonts = Vec::new();
+            for path in paths {
+                ok &= font_check(&load_sheet_or_exit(path), embedded_sheet_or_exit(path));
+                for font in psf_fonts_using(path) {
+                    if !fonts.iter().any(|(font_path, _)| *font_path == font.0) {
+                        fonts.push(font);
+                    }
+                }
+            }
+            // An edited sheet must not leave the fonts built from it behind
+            for (font_path, font) in fonts {
+                ok &= font_psf_check(&font_path, font);
+            }
+            if !ok {
+                exit(1);
+            }
+        }
+        Some("font-psf") => {
+            let paths = args.get(2..).unwrap_or(&[]);
+            if paths.is_empty() {
+                eprintln!("ERROR: no font is provided");
+                exit(1);
+            }
+            for path in paths {
+                if let Err(err) = font_psf(path, psf_font_or_exit(path)) {
+                    eprintln!("ERROR: {}", err);
+                    exit(1);
+                }
+            }
+        }
+        Some("font-psf-check") => {
+            let paths = args.get(2..).unwrap_or(&[]);
+            if paths.is_empty() {
+                eprintln!("ERROR: no font is provided");
+                exit(1);
+            }
+            let mut ok = true;
+            for path in paths {
+                ok &= font_psf_check(path, psf_font_or_exit(path));
+            }
+            if !ok {
+                exit(1);
+            }
+        }
+        _ => {
+            usage(program);
+            exit(1);
+        }
+    }
+}
@@ -1,59 +1,375 @@
+// Must match ABI_VERSION in game.rs
+const ABI_VERSION = 2;
+const DISPLAY_FORMAT_RGBA8888 = 1;
+// Capability bits, see CAPABILITY_* in game.rs
+const CAPABILITY_MOUSE = 1 << 0;
+const CAPABILITY_KEYBOARD = 1 << 1;
+const CAPABILITY_DIRTY_RECTS = 1 << 2;
+const CAPABILITY_LANGUAGES = 1 << 4;
+const CAPABILITY_TOUCH = 1 << 6;
+const CAPABILITY_GAMEPAD = 1 << 7;
+const CAPABILITY_BINDINGS = 1 << 8;
+const CAPABILITY_AUDIO = 1 << 9;
+// AbiDescriptor in game.rs, one u32 each in this order
+const ABI_DESCRIPTOR_FIELDS = [
+    "abiVersion", "size", "capabilities",
+    "displayWidth", "displayHeight", "displayFormat", "displayPixelsOffset",
+    "gameSize", "fontUploadCapacity", "languagesCount",
+    "inputQueueCapacity", "inputEventSize", "inputEventsOffset",
+    "audioSampleRate", "audioBufferCapacity", "audioSamplesOffset"
+];
+// InputKind in game.rs
+const INPUT_MOUSE_MOVE = 1;
+const INPUT_MOUSE_DOWN = 2;
+const INPUT_MOUSE_UP = 3;
+const INPUT_KEY_DOWN = 4;
+const INPUT_KEY_UP = 5;
+const INPUT_TOUCH_START = 6;
+const INPUT_TOUCH_MOVE = 7;
+const INPUT_TOUCH_END = 8;
+const INPUT_GAMEPAD_AXIS = 9;
+const INPUT_GAMEPAD_BUTTON_DOWN = 10;
+const INPUT_GAMEPAD_BUTTON_UP = 11;
+
+// Set once the game panics, nothing calls into it after that
+let panicMessage = null;
+// Indexed by the game's LogLevel: error, warn, info, debug
+const logLevels = [console.error, console.warn, console.info, console.debug];
+
+function readString(game, ptr, len) {
+    const bytes = new Uint8Array(game.instance.exports.memory.buffer, ptr, len);
+    return new TextDecoder().decode(bytes);
+}
+
+// Covers the canvas with a message for the player
+function showError(title, message) {
+    const canvas = document.getElementById("game-canvas");
+    const ctx = canvas.getContext("2d");
+    ctx.fillStyle = "rgba(0, 0, 0, 0.8)";
+    ctx.fillRect(0, 0, canvas.width, canvas.height);
+    ctx.fillStyle = "#FF4D6D";
+    ctx.font = "16px monospace";
+    ctx.fillText(title, 20, 40);
+    const lineLength = 80;
+    for (let i = 0; i * lineLength < message.length; ++i) {
+        ctx.fillText(message.slice(i * lineLength, (i + 1) * lineLength), 20, 70 + 20 * i);
+    }
+}
+
+// Refuses a game.wasm this file can't drive, rather than misreading its memory
+function readAbi(exports) {
+    const version = typeof exports.abi_version === "function" ? exports.abi_version() : "none";
+    if (version !== ABI_VERSION) {
+        throw new Error(`game.wasm has ABI version ${version}, index.js needs ${ABI_VERSION}`);
+    }
+    const ptr = exports.get_abi_descriptor();
+    const size = new Uint32Array(exports.memory.buffer, ptr, 2)[1];
+    // Newer builds may append fields this file doesn't know about yet
+    const words = new Uint32Array(exports.memory.buffer, ptr, size / 4);
+    const abi = {};
+    ABI_DESCRIPTOR_FIELDS.forEach((name, i) => {
+        abi[name] = words[i];
+    });
+    if (abi.displayFormat !== DISPLAY_FORMAT_RGBA8888) {
+        throw new Error(`game.wasm has display format ${abi.displayFormat}, index.js needs RGBA8888`);
+    }
+    abi.has = capability => (abi.capabilities & capability) !== 0;
+    return abi;
+}
+
+// Appends events to the game's input queue, which next_frame drains in order
+function inputQueue(exports, handle, abi) {
+    const queueAddr = exports.get_input_queue(handle);
+    return {
+        push(kind, time, code, x, y) {
+            // Views go stale whenever the wasm memory grows, so make new ones
+            const header = new Uint32Array(exports.memory.buffer, queueAddr, 2);
+            const [write, read] = header;
+            if (write - read >>> 0 >= abi.inputQueueCapacity) {
+                console.warn("Input queue is full, dropping an event");
+                return;
+            }
+            const slot = write % abi.inputQueueCapacity;
+            const event = new DataView(
+                exports.memory.buffer,
+                queueAddr + abi.inputEventsOffset + slot * abi.inputEventSize,
+                abi.inputEventSize
+            );
+            event.setUint32(0, kind, true);
+            event.setUint32(4, Math.round(time) >>> 0, true);
+            event.setInt32(8, code, true);
+            event.setFloat32(12, x, true);
+            event.setFloat32(16, y, true);
+            header[0] = write + 1;
+        }
+    };
+}
+
+// Gamepads have no events for sticks and buttons, so every frame this sends
+// what changed on the first standard gamepad since the last poll. A gamepad
+// that goes away lets go of everything it held.
+function gamepadPoller(input) {
+    let axes = [];
+    let buttons = [];
+    return {
+        poll(time) {
+            const pads = navigator.getGamepads ? Array.from(navigator.getGamepads()) : [];
+            const pad = pads.find(pad => pad && pad.connected && pad.mapping === "standard");
+            const nextAxes = pad ? Array.from(pad.axes) : axes.map(() => 0);
+            const nextButtons = pad ? Array.from(pad.buttons, button => button.value) : buttons.map(() => 0);
+            nextAxes.forEach((value, i) => {
+                if (value !== axes[i]) {
+                    input.push(INPUT_GAMEPAD_AXIS, time, i, value, 0);
+                }
+            });
+            nextButtons.forEach((value, i) => {
+                // Analog buttons send a new down event each time they move
+                if (value !== (buttons[i] ?? 0)) {
+                    input.push(value > 0 ? INPUT_GAMEPAD_BUTTON_DOWN : INPUT_GAMEPAD_BUTTON_UP, time, i, value, 0);
+                }
+            });
+            axes = nextAxes;
+            buttons = nextButtons;
+        }
+    };
+}
+
+// Hands the game's samples to the AudioWorklet in audio.js every frame.
+// Browsers keep audio off until the player interacts with the page, and the
+// samples made until then are thrown away.
+async function audioOutput(exports, handle, abi) {
+    const context = new AudioContext({ sampleRate: abi.audioSampleRate });
+    await context.audioWorklet.addModule("audio.js");
+    const node = new AudioWorkletNode(context, "game-audio", { outputChannelCount: [1] });
+    node.connect(context.destination);
+    for (const type of ["keydown", "mousedown", "touchstart"]) {
+        document.addEventListener(type, () => context.resume(), { once: true });
+    }
+    const bufferAddr = exports.get_audio_buffer(handle);
+    return {
+        drain() {
+            const header = new Uint32Array(exports.memory.buffer, bufferAddr, 2);
+            const [write, read] = header;
+            const count = write - read >>> 0;
+            if (count === 0) {
+                return;
+            }
+            const ring = new Float32Array(
+                exports.memory.buffer,
+                bufferAddr + abi.audioSamplesOffset,
+                abi.audioBufferCapacity
+            );
+            const samples = new Float32Array(count);
+            for (let i = 0; i < count; ++i) {
+                samples[i] = ring[(read + i >>> 0) % abi.audioBufferCapacity];
+            }
+            header[1] = write;
+            if (context.state === "running") {
+                node.port.postMessage(samples, [samples.buffer]);
+            }
+        }
+    };
+}
+
 async function start() {
     const game = await WebAssembly.instantiateStreaming(
         fetch("game.wasm"),
         {
             "env": {
                 "js_sin": Math.sin,
-                "js_cos": Math.cos
+                "js_cos": Math.cos,
+                "host_panic": (ptr, len) => {
+                    panicMessage = readString(game, ptr, len);
+                    console.error(panicMessage);
+                },
+                "host_log": (level, ptr, len) => {
+                    const log = logLevels[level] ?? console.log;
+                    log(readString(game, ptr, len));
+                }
             }
         }
     );
-    const memoryView = new Uint8Array(game.instance.exports.memory.buffer);
+    const abi = readAbi(game.instance.exports);
 
-    game.instance.exports.init();
-    const displayAddr = game.instance.exports.get_display();
-    const displayWidth = game.instance.exports.get_display_width();
-    const displayHeight = game.instance.exports.get_display_height();
+    // Creating a game grows the wasm memory, so views of it come after
+    const handle = game.instance.exports.create_game();
+    if (handle === 0) {
+        throw new Error("Not enough memory to create a game");
+    }
+    // Unknown or missing ids leave the game in English
+    const savedLanguage = localStorage.getItem("language");
+    if (savedLanguage !== null && abi.has(CAPABILITY_LANGUAGES)) {
+        game.instance.exports.set_language(handle, Number(savedLanguage));
+    }
+    // Saved as a [key, button] pair per action, in the game's action order
+    const savedBindings = localStorage.getItem("bindings");
+    if (savedBindings !== null && abi.has(CAPABILITY_BINDINGS)) {
+        try {
+            JSON.parse(savedBindings).forEach(([key, button], action) => {
+                game.instance.exports.set_key_binding(handle, action, Number(key));
+                game.instance.exports.set_button_binding(handle, action, Number(button));
+            });
+        } catch (error) {
+            console.warn("Ignoring saved bindings:", error);
+        }
+    }
+    function saveBindings() {
+        const bindings = [];
+        for (let action = 0; action < game.instance.exports.get_actions_count(); ++action) {
+            bindings.push([
+                game.instance.exports.get_key_binding(handle, action),
+                game.instance.exports.get_button_binding(handle, action)
+            ]);
+        }
+        localStorage.setItem("bindings", JSON.stringify(bindings));
+    }
+    // Saved as [stick, trigger], out of range values leave the defaults
+    const savedDeadZones = localStorage.getItem("gamepadDeadZones");
+    if (savedDeadZones !== null && abi.has(CAPABILITY_GAMEPAD)) {
+        try {
+            const [stick, trigger] = JSON.parse(savedDeadZones);
+            game.instance.exports.set_gamepad_dead_zones(handle, Number(stick), Number(trigger));
+        } catch (error) {
+            console.warn("Ignoring saved gamepad dead zones:", error);
+        }
+    }
+    const displayAddr = game.instance.exports.get_display(handle) + abi.displayPixelsOffset;
+    const displayWidth = abi.displayWidth;
+    const displayHeight = abi.displayHeight;
     const displaySize = displayWidth * displayHeight;
+    // Shares the pixels with wasm memory, so nothing is copied per frame
+    const frame = new ImageData(
+        new Uint8ClampedArray(
+            game.instance.exports.memory.buffer,
+            displayAddr,
+            4 * displaySize
+        ),
+        displayWidth, displayHeight
+    );
 
     const gameCanvas = document.getElementById("game-canvas");
-    document.addEventListener('keydown', e => {
-        console.log(e);
-        if (e.code === 'Space') {
-            game.instance.exports.toggle_pause_or_reset();
-        }
-    });
-    gameCanvas.addEventListener("mousemove", e => {
-        game.instance.exports.mouse_move(e.offsetX, e.offsetY);
-    });
-    gameCanvas.addEventListener("mousedown", e => {
-        game.instance.exports.mouse_click();
-    });
     const ctx = gameCanvas.getContext("2d");
+    const input = inputQueue(game.instance.exports, handle, abi);
+
+    // The canvas may be scaled down to fit the page, the game wants its own pixels
+    function gamePoint(e) {
+        const bounds = gameCanvas.getBoundingClientRect();
+        return [
+            (e.clientX - bounds.left) * displayWidth / bounds.width,
+            (e.clientY - bounds.top) * displayHeight / bounds.height
+        ];
+    }
+
+    // A panic traps the call that caused it. The message replaces the frozen
+    // frame and the game is left alone from then on.
+    function guarded(f) {
+        return (...args) => {
+            if (panicMessage !== null) {
+                return;
+            }
+            try {
+                f(...args);
+            } catch (error) {
+                panicMessage = panicMessage ?? String(error);
+                showError("The game crashed:", panicMessage);
+            }
+        };
+    }
+
+    document.addEventListener('keydown', guarded(e => {
+        // Language is a setting of the page, not something the game sees
+        if (e.code === 'KeyL' && abi.has(CAPABILITY_LANGUAGES)) {
+            const language = (game.instance.exports.get_language(handle) + 1) % abi.languagesCount;
+            game.instance.exports.set_language(handle, language);
+            localStorage.setItem("language", language);
+        } else if (!e.repeat && abi.has(CAPABILITY_KEYBOARD)) {
+            input.push(INPUT_KEY_DOWN, e.timeStamp, e.keyCode, 0, 0);
+        }
+    }));
+    document.addEventListener('keyup', guarded(e => {
+        if (abi.has(CAPABILITY_KEYBOARD)) {
+            input.push(INPUT_KEY_UP, e.timeStamp, e.keyCode, 0, 0);
+        }
+    }));
+    if (abi.has(CAPABILITY_MOUSE)) {
+        gameCanvas.addEventListener("mousemove", guarded(e => {
+            input.push(INPUT_MOUSE_MOVE, e.timeStamp, 0, ...gamePoint(e));
+        }));
+        gameCanvas.addEventListener("mousedown", guarded(e => {
+            input.push(INPUT_MOUSE_DOWN, e.timeStamp, e.button, ...gamePoint(e));
+        }));
+        gameCanvas.addEventListener("mouseup", guarded(e => {
+            input.push(INPUT_MOUSE_UP, e.timeStamp, e.button, ...gamePoint(e));
+        }));
+    }
+    if (abi.has(CAPABILITY_TOUCH)) {
+        // One event per finger that changed, told apart by the touch identifier
+        const touchListener = kind => guarded(e => {
+            // Keeps the browser from scrolling and from faking mouse events
+            e.preventDefault();
+            for (const touch of e.changedTouches) {
+                input.push(kind, e.timeStamp, touch.identifier, ...gamePoint(touch));
+            }
+        });
+        gameCanvas.addEventListener("touchstart", touchListener(INPUT_TOUCH_START), { passive: false });
+        gameCanvas.addEventListener("touchmove", touchListener(INPUT_TOUCH_MOVE), { passive: false });
+        gameCanvas.addEventListener("touchend", touchListener(INPUT_TOUCH_END), { passive: false });
+        gameCanvas.addEventListener("touchcancel", touchListener(INPUT_TOUCH_END), { passive: false });
+    }
+
+    const gamepad = abi.has(CAPABILITY_GAMEPAD) ? gamepadPoller(input) : null;
+    // The game plays on without sound if the browser can't give it any
+    let audio = null;
+    if (abi.has(CAPABILITY_AUDIO) && window.AudioWorkletNode) {
+        try {
+            audio = await audioOutput(game.instance.exports, handle, abi);
+        } catch (error) {
+            console.warn("No sound:", error);
+        }
+    }
 
     let start;
-    function step(timestamp) {
+    const step = guarded(timestamp => {
         if (start === undefined) {
             start = timestamp;
         }
         const dt = (timestamp - start) * 0.001;
         start = timestamp;
 
-        game.instance.exports.next_frame(dt);
-        const frame = new ImageData(
-            new Uint8ClampedArray(
-                memoryView.subarray(
-                    displayAddr,
-                    displayAddr + 4 * displaySize
-                )
-            ),
-            displayWidth, displayHeight
+        if (gamepad !== null) {
+            gamepad.poll(timestamp);
+        }
+        game.instance.exports.next_frame(handle, dt);
+        if (audio !== null) {
+            audio.drain();
+        }
+        // Rebinding happens in the game, saving is up to the page
+        if (abi.has(CAPABILITY_BINDINGS) && game.instance.exports.take_bindings_changed(handle)) {
+            saveBindings();
+        }
+        if (!abi.has(CAPABILITY_DIRTY_RECTS)) {
+            ctx.putImageData(frame, 0, 0);
+            window.requestAnimationFrame(step);
+            return;
+        }
+        // Only upload the rectangles that changed during this frame
+        const dirtyCount = game.instance.exports.get_dirty_rects_count(handle);
+        const dirtyRects = new Int32Array(
+            game.instance.exports.memory.buffer,
+            game.instance.exports.get_dirty_rects(handle),
+            4 * dirtyCount
         );
-        ctx.putImageData(frame, 0, 0);
+        for (let i = 0; i < dirtyCount; ++i) {
+            const [x, y, w, h] = dirtyRects.subarray(4 * i, 4 * i + 4);
+            ctx.putImageData(frame, 0, 0, x, y, w, h);
+        }
 
         window.requestAnimationFrame(step);
-    }
+    });
     window.requestAnimationFrame(step);
 }
 
-start().catch((e) => console.log(e));
\ No newline at end of file
+start().catch((e) => {
+    console.error(e);
+    showError("The game failed to start:", String(e));
+});
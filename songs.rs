@@ -0,0 +1,292 @@
+// Songs for the tracker in game.rs, kept as compact byte arrays.
+//
+// A song starts with six bytes: tempo in beats per minute, rows per pattern,
+// channels, number of patterns, length of the order and where in the order
+// to loop back to. The order follows, one pattern number per entry, and then
+// every pattern, row by row, with a cell per channel.
+//
+// A cell is a note, an instrument and an effect. Notes are MIDI numbers,
+// with __ to leave the channel alone and OFF to let go of the note.
+// Instruments are numbered from 1 in game.rs's INSTRUMENTS, 0 keeps the
+// channel's instrument. The effect's high nibble picks what it does and the
+// low one is its parameter, and it lasts until the channel's next note:
+//     0x1n  arpeggio, cycling through the note, n semitones up and a fifth up
+//     0x2n  volume, n out of 15
+//     0x3n  vibrato, n deep
+
+pub const SONGS_COUNT: usize = 3;
+pub const SONG_HEADER_SIZE: usize = 6;
+pub const SONG_CELL_SIZE: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(u32)]
+pub enum Song {
+    Title = 0,
+    Gameplay = 1,
+    Boss = 2,
+}
+
+impl Song {
+    pub const fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(Song::Title),
+            1 => Some(Song::Gameplay),
+            2 => Some(Song::Boss),
+            _ => None,
+        }
+    }
+
+    pub const fn data(self) -> &'static [u8] {
+        match self {
+            Song::Title => TITLE_SONG,
+            Song::Gameplay => GAMEPLAY_SONG,
+            Song::Boss => BOSS_SONG,
+        }
+    }
+}
+
+const __: u8 = 0;
+const OFF: u8 = 1;
+// MIDI note numbers, sharps spelled with an S
+const A1: u8 = 33;
+const AS1: u8 = 34;
+const C2: u8 = 36;
+const D2: u8 = 38;
+const E2: u8 = 40;
+const F2: u8 = 41;
+const G2: u8 = 43;
+const A2: u8 = 45;
+const AS2: u8 = 46;
+const C3: u8 = 48;
+const D3: u8 = 50;
+const E3: u8 = 52;
+const F3: u8 = 53;
+const G3: u8 = 55;
+const A3: u8 = 57;
+const AS3: u8 = 58;
+const C4: u8 = 60;
+const D4: u8 = 62;
+const E4: u8 = 64;
+const F4: u8 = 65;
+const G4: u8 = 67;
+const A4: u8 = 69;
+const AS4: u8 = 70;
+const B4: u8 = 71;
+const C5: u8 = 72;
+const CS5: u8 = 73;
+const D5: u8 = 74;
+const E5: u8 = 76;
+const F5: u8 = 77;
+const FS5: u8 = 78;
+const G5: u8 = 79;
+const A5: u8 = 81;
+const B5: u8 = 83;
+const C6: u8 = 84;
+const CS6: u8 = 85;
+const D6: u8 = 86;
+const C7: u8 = 96;
+
+// Calm loop under the title and controls screens
+const TITLE_SONG: &[u8] = &[
+    96, 16, 3, 4, 4, 0,
+    0, 1, 2, 3,
+    // Pattern 0: A minor
+    A4,  1, 0x00,   A2,  2, 0x00,   A3,  3, 0x13,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    C5,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    E5,  1, 0x00,   A2,  2, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    D5,  1, 0x00,   A2,  2, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    C5,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    B4,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   OFF, 0, 0x00,   __,  0, 0x00,
+    OFF, 0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    // Pattern 1: F major
+    A4,  1, 0x00,   F2,  2, 0x00,   F3,  3, 0x14,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    F4,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    A4,  1, 0x00,   F2,  2, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    C5,  1, 0x00,   F2,  2, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    A4,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    G4,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   OFF, 0, 0x00,   __,  0, 0x00,
+    OFF, 0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    // Pattern 2: C major
+    G4,  1, 0x00,   C3,  2, 0x00,   C4,  3, 0x14,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    E4,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    G4,  1, 0x00,   C3,  2, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    C5,  1, 0x00,   C3,  2, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    B4,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    G4,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   OFF, 0, 0x00,   __,  0, 0x00,
+    OFF, 0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    // Pattern 3: G major
+    B4,  1, 0x00,   G2,  2, 0x00,   G3,  3, 0x14,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    D5,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    B4,  1, 0x00,   G2,  2, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    G4,  1, 0x00,   G2,  2, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    OFF, 0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   OFF, 0, 0x00,   OFF, 0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+];
+
+// Plays while the difficulty ramps up, the game speeds it up along the way
+const GAMEPLAY_SONG: &[u8] = &[
+    132, 16, 4, 4, 4, 0,
+    0, 1, 2, 3,
+    // Pattern 0: E minor
+    E5,  1, 0x00,   E2,  2, 0x00,   E4,  3, 0x13,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   E3,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    G5,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   E2,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    B5,  1, 0x00,   E3,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    A5,  1, 0x00,   E2,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    G5,  1, 0x00,   E3,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    FS5, 1, 0x00,   E2,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    E5,  1, 0x00,   E3,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    // Pattern 1: C major
+    E5,  1, 0x00,   C2,  2, 0x00,   C4,  3, 0x14,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   C3,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    G5,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   C2,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    C6,  1, 0x00,   C3,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    B5,  1, 0x00,   C2,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    G5,  1, 0x00,   C3,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    E5,  1, 0x00,   C2,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   C3,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    // Pattern 2: G major
+    D5,  1, 0x00,   G2,  2, 0x00,   G4,  3, 0x14,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   G3,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    G5,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   G2,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    B5,  1, 0x00,   G3,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    D6,  1, 0x00,   G2,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    B5,  1, 0x00,   G3,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    G5,  1, 0x00,   G2,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    D5,  1, 0x00,   G3,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    // Pattern 3: D major
+    FS5, 1, 0x00,   D2,  2, 0x00,   D4,  3, 0x14,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    A5,  1, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   D2,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    D6,  1, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    CS6, 1, 0x00,   D2,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    A5,  1, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    FS5, 1, 0x00,   D2,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    OFF, 0, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   __,  0, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+];
+
+// Takes over once the enemies come as fast as they ever will
+const BOSS_SONG: &[u8] = &[
+    150, 16, 4, 3, 4, 0,
+    0, 1, 2, 2,
+    // Pattern 0: D minor
+    D5,  1, 0x32,   D2,  2, 0x00,   D4,  3, 0x13,   C2,  4, 0x00,
+    __,  0, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   D2,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   D2,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   D2,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    F5,  1, 0x32,   D2,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   D2,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    E5,  1, 0x32,   D2,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   D2,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   D3,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    // Pattern 1: B flat major
+    D5,  1, 0x32,   AS1, 2, 0x00,   AS3, 3, 0x14,   C2,  4, 0x00,
+    __,  0, 0x00,   AS2, 2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   AS1, 2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   AS2, 2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   AS1, 2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   AS2, 2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   AS1, 2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   AS2, 2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    AS4, 1, 0x32,   AS1, 2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   AS2, 2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   AS1, 2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   AS2, 2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    C5,  1, 0x32,   AS1, 2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   AS2, 2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   AS1, 2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   AS2, 2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    // Pattern 2: A major
+    CS5, 1, 0x34,   A1,  2, 0x00,   A3,  3, 0x14,   C2,  4, 0x00,
+    __,  0, 0x00,   A2,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   A1,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   A2,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   A1,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   A2,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   A1,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   A2,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    E5,  1, 0x34,   A1,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    __,  0, 0x00,   A2,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   A1,  2, 0x00,   __,  0, 0x00,   __,  0, 0x00,
+    __,  0, 0x00,   A2,  2, 0x00,   __,  0, 0x00,   C2,  4, 0x00,
+    A5,  1, 0x34,   A1,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   A2,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+    __,  0, 0x00,   A1,  2, 0x00,   __,  0, 0x00,   C6,  5, 0x00,
+    __,  0, 0x00,   A2,  2, 0x00,   __,  0, 0x00,   C7,  5, 0x23,
+];
@@ -0,0 +1,118 @@
+// UI text for every language the game ships with. Text is looked up by
+// message, so a new language is one more column in STRINGS.
+
+pub const LANGUAGES_COUNT: usize = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u32)]
+pub enum Language {
+    English = 0,
+    French = 1,
+    German = 2,
+    Spanish = 3,
+}
+
+impl Language {
+    pub const fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(Language::English),
+            1 => Some(Language::French),
+            2 => Some(Language::German),
+            3 => Some(Language::Spanish),
+            _ => None,
+        }
+    }
+
+    pub const fn id(self) -> u32 {
+        self as u32
+    }
+
+    pub fn text(self, message: Message) -> &'static str {
+        STRINGS.get(message as usize)
+            .and_then(|row| row.get(self as usize))
+            .copied()
+            .unwrap_or("")
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Message {
+    PressSpaceToStart,
+    ChangeLanguage,
+    Paused,
+    GameOver,
+    PressSpaceToRestart,
+    Score,
+    Copyright,
+    TapToStart,
+    TapToRestart,
+    OpenControls,
+    Controls,
+    MoveLeft,
+    MoveRight,
+    Fire,
+    Pause,
+    ControlsHelp,
+    PressKeyOrButton,
+    Level,
+}
+
+const MESSAGES_COUNT: usize = 18;
+
+// One row per message, in the order of Message, one column per language.
+// Score goes through the HUD font, which only has ASCII.
+const STRINGS: [[&str; LANGUAGES_COUNT]; MESSAGES_COUNT] = [
+    [
+        "Press Space to Start",
+        "Appuyez sur Espace pour commencer",
+        "Leertaste drücken zum Starten",
+        "Pulsa Espacio para empezar",
+    ],
+    ["L: English", "L: Français", "L: Deutsch", "L: Español"],
+    ["PAUSED", "PAUSE", "PAUSE", "PAUSA"],
+    ["GAME_OVER", "PARTIE TERMINÉE", "SPIEL VORBEI", "FIN DE LA PARTIDA"],
+    [
+        "Press Space to Restart",
+        "Appuyez sur Espace pour rejouer",
+        "Leertaste drücken für ein neues Spiel",
+        "Pulsa Espacio para volver a jugar",
+    ],
+    ["Score", "Score", "Punkte", "Puntos"],
+    [
+        "Made by realsanjeev",
+        "Créé par realsanjeev",
+        "Erstellt von realsanjeev",
+        "Hecho por realsanjeev",
+    ],
+    [
+        "Tap to Start",
+        "Touchez pour commencer",
+        "Tippen zum Starten",
+        "Toca para empezar",
+    ],
+    [
+        "Tap to Restart",
+        "Touchez pour rejouer",
+        "Tippen für ein neues Spiel",
+        "Toca para volver a jugar",
+    ],
+    ["C: Controls", "C: Commandes", "C: Steuerung", "C: Controles"],
+    ["CONTROLS", "COMMANDES", "STEUERUNG", "CONTROLES"],
+    ["Move left", "Gauche", "Links", "Izquierda"],
+    ["Move right", "Droite", "Rechts", "Derecha"],
+    ["Fire", "Tirer", "Schießen", "Disparar"],
+    ["Pause", "Pause", "Pause", "Pausa"],
+    [
+        "Enter: change   Esc: back",
+        "Entrée : changer   Échap : retour",
+        "Enter: ändern   Esc: zurück",
+        "Intro: cambiar   Esc: volver",
+    ],
+    [
+        "Press a key or button, Esc to cancel",
+        "Appuyez sur une touche ou un bouton, Échap pour annuler",
+        "Taste oder Knopf drücken, Esc zum Abbrechen",
+        "Pulsa una tecla o un botón, Esc para cancelar",
+    ],
+    ["LEVEL", "NIVEAU", "LEVEL", "NIVEL"],
+];